@@ -0,0 +1,165 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use cairo::ImageSurface;
+
+use crate::draw::load_png;
+
+/// What happens after the last frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Playback {
+    Loop,
+    /// Stop on the last frame, like plymouth's end-of-boot animation.
+    Once,
+}
+
+/// A numbered frame sequence such as two-step's `throbber-0001.png`,
+/// `throbber-0002.png`, …, played on a clock that starts when it is loaded.
+///
+/// Frames are picked by elapsed time rather than by counting redraws, so a
+/// slow frame skips ahead instead of slowing the animation down.
+pub struct Animation {
+    frames: Vec<ImageSurface>,
+    interval: Duration,
+    playback: Playback,
+    started: Instant,
+}
+
+impl Animation {
+    /// Loads `<prefix>-0001.png` onwards from `dir` until a number is
+    /// missing; `None` if there is no first frame.
+    pub fn load(dir: &Path, prefix: &str, frame_rate: f32, playback: Playback) -> Option<Self> {
+        let frames: Vec<_> = (1..)
+            .map_while(|n| load_png(&dir.join(format!("{prefix}-{n:04}.png"))))
+            .collect();
+        Self::new(frames, frame_rate, playback)
+    }
+
+    pub fn new(frames: Vec<ImageSurface>, frame_rate: f32, playback: Playback) -> Option<Self> {
+        if frames.is_empty() || frame_rate <= 0.0 {
+            return None;
+        }
+        Some(Self {
+            frames,
+            interval: Duration::from_nanos((1e9 / frame_rate as f64).round() as u64),
+            playback,
+            started: Instant::now(),
+        })
+    }
+
+    fn index_at(&self, elapsed: Duration) -> usize {
+        let n = (elapsed.as_nanos() / self.interval.as_nanos().max(1)) as usize;
+        match self.playback {
+            Playback::Loop => n % self.frames.len(),
+            Playback::Once => n.min(self.frames.len() - 1),
+        }
+    }
+
+    pub fn frame(&self) -> &ImageSurface {
+        &self.frames[self.index_at(self.started.elapsed())]
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.playback == Playback::Once
+            && self.started.elapsed() >= self.interval * (self.frames.len() - 1) as u32
+    }
+
+    /// Time until the next frame is due, or `None` once the animation has
+    /// come to rest.
+    pub fn next_frame_in(&self) -> Option<Duration> {
+        if self.frames.len() < 2 || self.is_finished() {
+            return None;
+        }
+        let elapsed = self.started.elapsed().as_nanos();
+        let interval = self.interval.as_nanos().max(1);
+        let left = interval - elapsed % interval;
+        Some(Duration::from_nanos(left as u64))
+    }
+}
+
+/// The animations of a two-step theme.
+#[derive(Default)]
+pub struct ThemeAnimations {
+    /// Loops for as long as the greeter runs, continuing the boot throbber.
+    pub throbber: Option<Animation>,
+    /// Plays once and then keeps showing its last frame.
+    pub animation: Option<Animation>,
+}
+
+impl ThemeAnimations {
+    pub fn load(image_dir: &str, frame_rate: f32) -> Self {
+        if image_dir.is_empty() {
+            return Self::default();
+        }
+        let dir = Path::new(image_dir);
+        Self {
+            throbber: Animation::load(dir, "throbber", frame_rate, Playback::Loop),
+            animation: Animation::load(dir, "animation", frame_rate, Playback::Once),
+        }
+    }
+
+    /// The animation on screen: like plymouth, the end animation takes the
+    /// throbber's place when the theme has one.
+    pub fn current(&self) -> Option<&Animation> {
+        self.animation.as_ref().or(self.throbber.as_ref())
+    }
+
+    /// Time until the animation on screen needs a new frame.
+    pub fn next_frame_in(&self) -> Option<Duration> {
+        self.current().and_then(Animation::next_frame_in)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cairo::Format;
+
+    fn frames(count: usize) -> Vec<ImageSurface> {
+        (0..count)
+            .map(|_| ImageSurface::create(Format::ARgb32, 1, 1).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn frames_advance_with_time() {
+        let second = Duration::from_secs(1);
+        let looped = Animation::new(frames(3), 10.0, Playback::Loop).unwrap();
+        assert_eq!(looped.index_at(Duration::ZERO), 0);
+        assert_eq!(looped.index_at(second / 10 * 2), 2);
+        assert_eq!(looped.index_at(second / 10 * 4), 1);
+
+        let once = Animation::new(frames(3), 10.0, Playback::Once).unwrap();
+        assert_eq!(once.index_at(second / 10 * 4), 2);
+        assert_eq!(once.index_at(second * 60), 2);
+    }
+
+    #[test]
+    fn the_end_animation_replaces_the_throbber() {
+        let throbber = || Animation::new(frames(2), 10.0, Playback::Loop);
+        let mut animations = ThemeAnimations {
+            throbber: throbber(),
+            animation: None,
+        };
+        assert_eq!(animations.current().unwrap().playback, Playback::Loop);
+        animations.animation = Animation::new(frames(3), 10.0, Playback::Once);
+        assert_eq!(animations.current().unwrap().playback, Playback::Once);
+        // Once the end animation rests, the throbber keeps no timer running.
+        animations.animation.as_mut().unwrap().started -= Duration::from_secs(1);
+        assert!(animations.next_frame_in().is_none());
+    }
+
+    #[test]
+    fn sequence_stops_at_the_first_missing_frame() {
+        let dir = std::env::temp_dir().join(format!("ndlm-animation-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for n in [1, 2, 4] {
+            let mut file = std::fs::File::create(dir.join(format!("throbber-{n:04}.png"))).unwrap();
+            frames(1)[0].write_to_png(&mut file).unwrap();
+        }
+        let throbber = Animation::load(&dir, "throbber", 30.0, Playback::Loop).unwrap();
+        assert_eq!(throbber.frames.len(), 2);
+        assert!(Animation::load(&dir, "animation", 30.0, Playback::Once).is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
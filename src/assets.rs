@@ -0,0 +1,34 @@
+use std::path::Path;
+
+use cairo::ImageSurface;
+
+use crate::draw::load_png;
+
+/// Images of a plymouth two-step theme, read from its `ImageDir`.
+///
+/// Every image is optional; the login dialog falls back to plain text when
+/// `entry.png` is missing.
+#[derive(Default)]
+pub struct TwoStepAssets {
+    pub entry: Option<ImageSurface>,
+    pub bullet: Option<ImageSurface>,
+    pub lock: Option<ImageSurface>,
+    pub dialog_box: Option<ImageSurface>,
+    pub background_tile: Option<ImageSurface>,
+}
+
+impl TwoStepAssets {
+    pub fn load(image_dir: &str) -> Self {
+        if image_dir.is_empty() {
+            return Self::default();
+        }
+        let dir = Path::new(image_dir);
+        Self {
+            entry: load_png(&dir.join("entry.png")),
+            bullet: load_png(&dir.join("bullet.png")),
+            lock: load_png(&dir.join("lock.png")),
+            dialog_box: load_png(&dir.join("box.png")),
+            background_tile: load_png(&dir.join("background-tile.png")),
+        }
+    }
+}
@@ -0,0 +1,398 @@
+use std::str::FromStr;
+
+use cairo::{Context as CairoContext, Extend, Filter, Format, ImageSurface, SurfacePattern};
+use serde::{Deserialize, Deserializer, de};
+
+use crate::color::Color;
+use crate::draw::DrawError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum GradientDirection {
+    /// Start color at the top, end color at the bottom, as plymouth does.
+    #[default]
+    Vertical,
+    Horizontal,
+}
+
+impl FromStr for GradientDirection {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "vertical" => Ok(GradientDirection::Vertical),
+            "horizontal" => Ok(GradientDirection::Horizontal),
+            _ => Err(format!("{s:?} is not vertical or horizontal")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for GradientDirection {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// How a background image is fitted to the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ImageMode {
+    /// Scaled to cover the screen, cropping what sticks out.
+    #[default]
+    Fill,
+    /// Scaled to fit inside the screen, with the gradient around it.
+    Fit,
+    /// Scaled to the screen size, ignoring its aspect ratio.
+    Stretch,
+    /// Unscaled in the middle of the screen.
+    Center,
+    /// Repeated from the top left corner.
+    Tile,
+}
+
+impl FromStr for ImageMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "fill" => Ok(ImageMode::Fill),
+            "fit" => Ok(ImageMode::Fit),
+            "stretch" => Ok(ImageMode::Stretch),
+            "center" => Ok(ImageMode::Center),
+            "tile" => Ok(ImageMode::Tile),
+            _ => Err(format!("{s:?} is not fill, fit, stretch, center or tile")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ImageMode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// 4x4 Bayer matrix; entry / 16 - 0.5 gives the ordered-dither threshold.
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// The full-screen backdrop: gradient plus optional background tile.
+///
+/// Rendering it pixel by pixel is too slow to repeat per keystroke, so it is
+/// built once per resolution and painted from this cache.
+pub struct Background {
+    size: (u32, u32),
+    surface: ImageSurface,
+}
+
+/// A background image and how to draw it over the gradient.
+pub struct Picture<'a> {
+    pub image: &'a ImageSurface,
+    pub mode: ImageMode,
+    /// Painted over the whole background, e.g. to darken it behind text.
+    pub tint: Option<Color>,
+    /// Box blur radius in pixels.
+    pub blur: u32,
+}
+
+pub struct Gradient {
+    pub start: Color,
+    pub end: Color,
+    pub direction: GradientDirection,
+    /// Ordered dithering hides banding on panels with 8 bits per channel.
+    pub dither: bool,
+}
+
+impl Background {
+    pub fn new(
+        size: (u32, u32),
+        gradient: &Gradient,
+        tile: Option<&ImageSurface>,
+        picture: Option<&Picture<'_>>,
+    ) -> Result<Self, DrawError> {
+        let (width, height) = (size.0 as usize, size.1 as usize);
+        let mut data = vec![0u8; width * height * 4];
+        let span = match gradient.direction {
+            GradientDirection::Vertical => height,
+            GradientDirection::Horizontal => width,
+        }
+        .saturating_sub(1)
+        .max(1) as f32;
+        let (start, end) = (&gradient.start, &gradient.end);
+        for (y, row) in data.chunks_mut(width * 4).enumerate() {
+            for (x, pixel) in row.chunks_mut(4).enumerate() {
+                let t = match gradient.direction {
+                    GradientDirection::Vertical => y as f32 / span,
+                    GradientDirection::Horizontal => x as f32 / span,
+                };
+                let threshold = if gradient.dither {
+                    BAYER[y % 4][x % 4] as f32 / 16.0 - 0.5
+                } else {
+                    0.0
+                };
+                let channel = |a: f32, b: f32| {
+                    ((a + (b - a) * t) * 255.0 + threshold)
+                        .round()
+                        .clamp(0.0, 255.0) as u8
+                };
+                // XRGB8888 in little-endian byte order.
+                pixel[0] = channel(start.blue, end.blue);
+                pixel[1] = channel(start.green, end.green);
+                pixel[2] = channel(start.red, end.red);
+                pixel[3] = 0xff;
+            }
+        }
+        let surface = ImageSurface::create_for_data(
+            data,
+            Format::Rgb24,
+            size.0 as i32,
+            size.1 as i32,
+            size.0 as i32 * 4,
+        )?;
+        if let Some(tile) = tile.filter(|t| t.width() > 0 && t.height() > 0) {
+            let context = CairoContext::new(&surface)?;
+            context.scale(
+                size.0 as f64 / tile.width() as f64,
+                size.1 as f64 / tile.height() as f64,
+            );
+            context.set_source_surface(tile, 0.0, 0.0)?;
+            context.paint()?;
+        }
+        if let Some(picture) = picture {
+            let layer = picture_layer(size, picture)?;
+            let context = CairoContext::new(&surface)?;
+            context.set_source_surface(&layer, 0.0, 0.0)?;
+            context.paint()?;
+            if let Some(tint) = picture.tint {
+                context.set_source_rgba(
+                    tint.red as f64,
+                    tint.green as f64,
+                    tint.blue as f64,
+                    tint.opacity as f64,
+                );
+                context.paint()?;
+            }
+        }
+        Ok(Self { size, surface })
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    pub fn surface(&self) -> &ImageSurface {
+        &self.surface
+    }
+}
+
+/// Draws `picture` onto a transparent screen-sized layer, scaled by its
+/// mode and blurred.
+fn picture_layer(size: (u32, u32), picture: &Picture<'_>) -> Result<ImageSurface, DrawError> {
+    let mut layer = ImageSurface::create(Format::ARgb32, size.0 as i32, size.1 as i32)?;
+    let image = picture.image;
+    if image.width() > 0 && image.height() > 0 {
+        let context = CairoContext::new(&layer)?;
+        let (width, height) = (size.0 as f64, size.1 as f64);
+        let (image_w, image_h) = (image.width() as f64, image.height() as f64);
+        let (scale_x, scale_y) = match picture.mode {
+            ImageMode::Fill => {
+                let scale = (width / image_w).max(height / image_h);
+                (scale, scale)
+            }
+            ImageMode::Fit => {
+                let scale = (width / image_w).min(height / image_h);
+                (scale, scale)
+            }
+            ImageMode::Stretch => (width / image_w, height / image_h),
+            ImageMode::Center | ImageMode::Tile => (1.0, 1.0),
+        };
+        if picture.mode != ImageMode::Tile {
+            context.translate(
+                ((width - image_w * scale_x) / 2.0).round(),
+                ((height - image_h * scale_y) / 2.0).round(),
+            );
+            context.scale(scale_x, scale_y);
+        }
+        let pattern = SurfacePattern::create(image);
+        pattern.set_filter(Filter::Good);
+        if picture.mode == ImageMode::Tile {
+            pattern.set_extend(Extend::Repeat);
+        } else {
+            // Padding keeps the edges from fading into the gradient when
+            // scaled up; the clip keeps the padding itself off screen.
+            pattern.set_extend(Extend::Pad);
+            context.rectangle(0.0, 0.0, image_w, image_h);
+            context.clip();
+        }
+        context.set_source(&pattern)?;
+        context.paint()?;
+    }
+    if picture.blur > 0 {
+        let (width, height, stride) = (
+            layer.width() as usize,
+            layer.height() as usize,
+            layer.stride() as usize,
+        );
+        // A wider window than the layer only averages more copies of the
+        // edge pixels, so the radius is capped to keep the sums small.
+        let radius = (picture.blur as usize).min(width.max(height));
+        let mut data = layer.data().map_err(|_| cairo::Error::SurfaceFinished)?;
+        // Three box blurs come close to a gaussian one.
+        for _ in 0..3 {
+            box_blur(&mut data, (width, height), (4, stride), radius);
+            box_blur(&mut data, (height, width), (stride, 4), radius);
+        }
+    }
+    Ok(layer)
+}
+
+/// Averages each pixel with the `radius` pixels on either side along one
+/// axis. `lines` is the length and count of the lines, `steps` the byte
+/// distance between pixels of a line and between lines.
+fn box_blur(
+    data: &mut [u8],
+    (length, lines): (usize, usize),
+    steps: (usize, usize),
+    radius: usize,
+) {
+    let (pixel_step, line_step) = steps;
+    let window = (2 * radius + 1) as u32;
+    let mut line = vec![[0u8; 4]; length];
+    for l in 0..lines {
+        let at = |i: usize| l * line_step + i * pixel_step;
+        for (i, pixel) in line.iter_mut().enumerate() {
+            pixel.copy_from_slice(&data[at(i)..at(i) + 4]);
+        }
+        // Edge pixels are repeated past the ends of the line.
+        let clamped = |i: isize| line[i.clamp(0, length as isize - 1) as usize];
+        let mut sum = [0u32; 4];
+        for i in -(radius as isize)..=radius as isize {
+            for (s, c) in sum.iter_mut().zip(clamped(i)) {
+                *s += c as u32;
+            }
+        }
+        for i in 0..length {
+            for (c, s) in data[at(i)..at(i) + 4].iter_mut().zip(sum) {
+                *c = ((s + window / 2) / window) as u8;
+            }
+            let (add, sub) = (
+                clamped(i as isize + radius as isize + 1),
+                clamped(i as isize - radius as isize),
+            );
+            for ((s, a), r) in sum.iter_mut().zip(add).zip(sub) {
+                *s = *s + a as u32 - r as u32;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(background: &mut Background, x: usize, y: usize) -> [u8; 3] {
+        let stride = background.surface.stride() as usize;
+        let data = background.surface.data().unwrap();
+        let i = y * stride + x * 4;
+        [data[i + 2], data[i + 1], data[i]]
+    }
+
+    #[test]
+    fn gradient_runs_from_start_to_end_color() {
+        let gradient = Gradient {
+            start: "0xff0000".parse().unwrap(),
+            end: "0x0000ff".parse().unwrap(),
+            direction: GradientDirection::Vertical,
+            dither: false,
+        };
+        let mut background = Background::new((4, 3), &gradient, None, None).unwrap();
+        assert_eq!(pixel(&mut background, 3, 0), [255, 0, 0]);
+        assert_eq!(pixel(&mut background, 0, 1), [128, 0, 128]);
+        assert_eq!(pixel(&mut background, 3, 2), [0, 0, 255]);
+
+        let gradient = Gradient {
+            direction: GradientDirection::Horizontal,
+            ..gradient
+        };
+        let mut background = Background::new((3, 4), &gradient, None, None).unwrap();
+        assert_eq!(pixel(&mut background, 0, 3), [255, 0, 0]);
+        assert_eq!(pixel(&mut background, 2, 0), [0, 0, 255]);
+    }
+
+    /// A 2x1 image, red on the left and blue on the right.
+    fn red_blue() -> ImageSurface {
+        let image = ImageSurface::create(Format::ARgb32, 2, 1).unwrap();
+        let context = CairoContext::new(&image).unwrap();
+        context.set_source_rgb(1.0, 0.0, 0.0);
+        context.rectangle(0.0, 0.0, 1.0, 1.0);
+        context.fill().unwrap();
+        context.set_source_rgb(0.0, 0.0, 1.0);
+        context.rectangle(1.0, 0.0, 1.0, 1.0);
+        context.fill().unwrap();
+        drop(context);
+        image
+    }
+
+    fn with_picture(mode: ImageMode, tint: Option<Color>, blur: u32) -> Background {
+        let gradient = Gradient {
+            start: Color::default(),
+            end: Color::default(),
+            direction: GradientDirection::Vertical,
+            dither: false,
+        };
+        let image = red_blue();
+        let picture = Picture {
+            image: &image,
+            mode,
+            tint,
+            blur,
+        };
+        Background::new((8, 8), &gradient, None, Some(&picture)).unwrap()
+    }
+
+    /// Which of red, blue or neither a scaled pixel mostly is.
+    fn shade(background: &mut Background, x: usize, y: usize) -> char {
+        match pixel(background, x, y) {
+            [r, _, b] if r > 200 && b < 50 => 'r',
+            [r, _, b] if b > 200 && r < 50 => 'b',
+            [0, 0, 0] => 'k',
+            _ => '?',
+        }
+    }
+
+    #[test]
+    fn images_are_fitted_by_mode() {
+        // Fit letterboxes the 2:1 image: black above, red and blue in the middle.
+        let mut fit = with_picture(ImageMode::Fit, None, 0);
+        assert_eq!(shade(&mut fit, 0, 0), 'k');
+        assert_eq!(shade(&mut fit, 1, 4), 'r');
+        assert_eq!(shade(&mut fit, 6, 4), 'b');
+        assert_eq!(shade(&mut fit, 6, 7), 'k');
+        // Fill crops the sides instead, so the screen is covered.
+        let mut fill = with_picture(ImageMode::Fill, None, 0);
+        assert_eq!(shade(&mut fill, 0, 0), 'r');
+        assert_eq!(shade(&mut fill, 7, 7), 'b');
+        let mut center = with_picture(ImageMode::Center, None, 0);
+        assert_eq!(shade(&mut center, 3, 4), 'r');
+        assert_eq!(shade(&mut center, 4, 4), 'b');
+        assert_eq!(shade(&mut center, 2, 4), 'k');
+        let mut tile = with_picture(ImageMode::Tile, None, 0);
+        assert_eq!(shade(&mut tile, 6, 7), 'r');
+        assert_eq!(shade(&mut tile, 7, 0), 'b');
+    }
+
+    #[test]
+    fn tint_and_blur_soften_the_image() {
+        let half_black = "0x00000080".parse().ok();
+        let mut tinted = with_picture(ImageMode::Fit, half_black, 0);
+        assert_eq!(pixel(&mut tinted, 0, 4), [127, 0, 0]);
+        let mut sharp = with_picture(ImageMode::Fit, None, 0);
+        let mut blurred = with_picture(ImageMode::Fit, None, 2);
+        assert_eq!(pixel(&mut sharp, 0, 1), [0, 0, 0]);
+        let [red, _, blue] = pixel(&mut blurred, 0, 1);
+        assert!(red > 0 && blue < red, "{red} {blue}");
+        // An absurd radius is capped to the layer, leaving the screen
+        // smeared into nearly one color rather than hanging.
+        let mut smeared = with_picture(ImageMode::Fit, None, u32::MAX);
+        let ([left, _, _], [right, _, _]) = (pixel(&mut smeared, 0, 4), pixel(&mut smeared, 7, 4));
+        assert!(left.abs_diff(right) < 8, "{left} {right}");
+    }
+}
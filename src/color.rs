@@ -1,5 +1,8 @@
 use std::str::FromStr;
 
+use serde::{Deserialize, Deserializer, de};
+use thiserror::Error;
+
 #[derive(Debug, Clone, Copy, Default)]
 pub struct Color {
     pub red: f32,
@@ -17,22 +20,43 @@ const fn rgb(red: f32, green: f32, blue: f32) -> Color {
     }
 }
 
+#[derive(Error, Debug)]
+#[error("invalid color {0:?}, expected 0xRRGGBB or 0xRRGGBBAA")]
+pub struct ParseColorError(String);
+
 impl FromStr for Color {
-    type Err = crate::Error;
+    type Err = ParseColorError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let hex = s.strip_prefix("0x").unwrap();
-        match u32::from_str_radix(hex, 16) {
-            Ok(value) => {
-                let red = ((value >> 16) & 0xFF) as f32 / 255.0;
-                let green = ((value >> 8) & 0xFF) as f32 / 255.0;
-                let blue = (value & 0xFF) as f32 / 255.0;
-                Ok(rgb(red, green, blue))
+        let hex = s.strip_prefix("0x").or_else(|| s.strip_prefix('#'));
+        let hex = hex.filter(|h| h.len() == 6 || h.len() == 8);
+        match hex.map(|h| (h.len(), u32::from_str_radix(h, 16))) {
+            Some((len, Ok(value))) => {
+                // Without an alpha byte the color is opaque.
+                let value = if len == 6 { value << 8 | 0xFF } else { value };
+                let channel = |shift: u32| ((value >> shift) & 0xFF) as f32 / 255.0;
+                Ok(Color {
+                    red: channel(24),
+                    green: channel(16),
+                    blue: channel(8),
+                    opacity: channel(0),
+                })
             }
-            Err(_) => Ok(rgb(255f32, 0f32, 0f32)),
+            _ => Err(ParseColorError(s.to_string())),
         }
     }
 }
+
+/// Colors in native themes are the same hex strings as in plymouth themes.
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
 impl Color {
     pub const WHITE: Self = rgb(1.0, 1.0, 1.0);
+    pub const BLACK: Self = rgb(0.0, 0.0, 0.0);
     pub const YELLOW: Self = rgb(0.75, 0.75, 0.25);
+    pub const RED: Self = rgb(0.9, 0.3, 0.3);
 }
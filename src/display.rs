@@ -0,0 +1,400 @@
+//! Choosing the DRM card, connectors, modes and CRTCs the greeter is
+//! shown on.
+
+use std::path::{Path, PathBuf};
+
+use drm::control::Device as ControlDevice;
+use drm::control::{Mode, ModeTypeFlags, ResourceHandles, connector, crtc};
+use thiserror::Error;
+
+use crate::output::Card;
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum DisplayError {
+    #[error("could not query DRM resources: {0}")]
+    Drm(#[from] std::io::Error),
+    #[error("no connected display")]
+    NoConnector,
+    #[error("{0} has no modes")]
+    NoMode(String),
+    #[error("no CRTC can drive {0}")]
+    NoCrtc(String),
+    #[error("could not open {0}: {1}")]
+    OpenCard(PathBuf, std::io::Error),
+    #[error("no DRM card in {DRI_DIR} can drive a display")]
+    NoCard,
+}
+
+const DRI_DIR: &str = "/dev/dri";
+const SYS_DRM_DIR: &str = "/sys/class/drm";
+
+/// Which screen the login dialog is drawn on; the others show the
+/// background only.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum DialogPlacement {
+    /// The primary output, from `--connector` or the first connected one.
+    #[default]
+    Primary,
+    /// The output the mouse was last moved onto, with the outputs side by
+    /// side in connector order.
+    Pointer,
+    /// Every output.
+    Mirror,
+}
+
+impl std::str::FromStr for DialogPlacement {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "primary" => Ok(DialogPlacement::Primary),
+            "pointer" => Ok(DialogPlacement::Pointer),
+            "mirror" => Ok(DialogPlacement::Mirror),
+            _ => Err(format!("{s:?} is not primary, pointer or mirror")),
+        }
+    }
+}
+
+/// Overrides for the automatic choice, from `--card`, `--connector`,
+/// `--resolution` and `--dialog`.
+#[derive(Default, Clone)]
+pub struct OutputChoice {
+    /// Card such as `card1` or `/dev/dri/card1`.
+    pub card: Option<String>,
+    /// Primary connector name such as `eDP-1` or `HDMI-A-2`.
+    pub connector: Option<String>,
+    /// Mode size for the primary output.
+    pub resolution: Option<(u32, u32)>,
+    pub dialog: DialogPlacement,
+}
+
+/// What card selection looks at.
+#[derive(Debug)]
+struct CardInfo {
+    name: String,
+    /// Set by the firmware on the GPU it booted with.
+    boot_vga: bool,
+    /// Connected connectors, or `None` for a render-only device that has no
+    /// connectors or CRTCs.
+    connected: Option<usize>,
+}
+
+impl CardInfo {
+    fn probe(name: String, card: &Card) -> Self {
+        let boot_vga =
+            std::fs::read_to_string(Path::new(SYS_DRM_DIR).join(&name).join("device/boot_vga"))
+                .is_ok_and(|value| value.trim() == "1");
+        let connected = card
+            .resource_handles()
+            .ok()
+            .filter(|resources| !resources.connectors().is_empty() && !resources.crtcs().is_empty())
+            .map(|resources| {
+                resources
+                    .connectors()
+                    .iter()
+                    .filter_map(|&handle| card.get_connector(handle, false).ok())
+                    .filter(|info| info.state() == connector::State::Connected)
+                    .count()
+            });
+        CardInfo {
+            name,
+            boot_vga,
+            connected,
+        }
+    }
+
+    fn describe(&self) -> String {
+        let Some(connected) = self.connected else {
+            return "render-only, skipped".to_string();
+        };
+        let displays = match connected {
+            0 => "no connected display".to_string(),
+            1 => "1 connected display".to_string(),
+            n => format!("{n} connected displays"),
+        };
+        if self.boot_vga {
+            format!("boot VGA, {displays}")
+        } else {
+            displays
+        }
+    }
+}
+
+/// The card with connected displays, preferring the boot VGA one and then
+/// the lowest number. Render-only cards are never picked.
+fn pick_card(cards: &[CardInfo]) -> Option<usize> {
+    (0..cards.len())
+        .rev()
+        .filter(|&i| cards[i].connected.is_some())
+        .max_by_key(|&i| (cards[i].connected > Some(0), cards[i].boot_vga))
+}
+
+fn open_rw(path: &Path) -> Result<Card, DisplayError> {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .map(Card::new)
+        .map_err(|err| DisplayError::OpenCard(path.to_path_buf(), err))
+}
+
+/// Opens the card named by `wanted`, or else the best `card*` device in
+/// `/dev/dri`. Every card considered is listed on stderr with why it was
+/// or wasn't used.
+pub fn open_card(wanted: Option<&str>) -> Result<Card, DisplayError> {
+    if let Some(wanted) = wanted {
+        return open_rw(&Path::new(DRI_DIR).join(wanted));
+    }
+    let mut names: Vec<String> = std::fs::read_dir(DRI_DIR)
+        .map_err(|err| DisplayError::OpenCard(DRI_DIR.into(), err))?
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with("card"))
+        .collect();
+    names.sort_by_key(|name| name["card".len()..].parse::<u32>().unwrap_or(u32::MAX));
+
+    let mut cards = Vec::new();
+    let mut infos = Vec::new();
+    for name in names {
+        match open_rw(&Path::new(DRI_DIR).join(&name)) {
+            Ok(card) => {
+                let info = CardInfo::probe(name, &card);
+                eprintln!("{}: {}", info.name, info.describe());
+                cards.push(card);
+                infos.push(info);
+            }
+            Err(err) => eprintln!("{name}: skipped, {err}"),
+        }
+    }
+    let index = pick_card(&infos).ok_or(DisplayError::NoCard)?;
+    eprintln!("using {}", infos[index].name);
+    Ok(cards.swap_remove(index))
+}
+
+/// The connector to drive and how.
+pub struct Display {
+    pub connector: connector::Handle,
+    pub name: String,
+    pub mode: Mode,
+    pub crtc: crtc::Handle,
+}
+
+/// The name the kernel and compositors use for a connector, e.g. `DP-2`.
+pub fn connector_name(info: &connector::Info) -> String {
+    format!("{}-{}", info.interface().as_str(), info.interface_id())
+}
+
+/// What mode selection looks at.
+struct ModeInfo {
+    size: (u32, u32),
+    refresh: u32,
+    preferred: bool,
+}
+
+impl From<&Mode> for ModeInfo {
+    fn from(mode: &Mode) -> Self {
+        let (width, height) = mode.size();
+        ModeInfo {
+            size: (width as u32, height as u32),
+            refresh: mode.vrefresh(),
+            preferred: mode.mode_type().contains(ModeTypeFlags::PREFERRED),
+        }
+    }
+}
+
+/// The connector named `wanted` if it is connected, or else the first
+/// connected one. `connectors` holds each name and whether it is connected.
+fn pick_connector(connectors: &[(String, bool)], wanted: Option<&str>) -> Option<usize> {
+    if let Some(wanted) = wanted {
+        match connectors
+            .iter()
+            .position(|(name, _)| name.eq_ignore_ascii_case(wanted))
+        {
+            Some(index) if connectors[index].1 => return Some(index),
+            Some(_) => eprintln!("connector {wanted} is not connected"),
+            None => eprintln!("no connector named {wanted}"),
+        }
+    }
+    connectors.iter().position(|(_, connected)| *connected)
+}
+
+/// The mode of `resolution` with the highest refresh rate, or else the
+/// preferred mode, or else the largest.
+fn pick_mode(modes: &[ModeInfo], resolution: Option<(u32, u32)>) -> Option<usize> {
+    if let Some(resolution) = resolution {
+        let matching = (0..modes.len()).filter(|&i| modes[i].size == resolution);
+        if let Some(index) = matching.max_by_key(|&i| (modes[i].refresh, modes[i].preferred)) {
+            return Some(index);
+        }
+        eprintln!(
+            "no {}x{} mode, using the preferred one",
+            resolution.0, resolution.1
+        );
+    }
+    (0..modes.len()).max_by_key(|&i| {
+        let mode = &modes[i];
+        (mode.preferred, mode.size.0 * mode.size.1, mode.refresh)
+    })
+}
+
+/// The CRTC already driving the connector if there is one, so the console's
+/// setup is reused, or else the first one its encoders can use. CRTCs
+/// `taken` by other outputs are skipped.
+fn pick_crtc<T: Copy + PartialEq>(current: Option<T>, possible: &[T], taken: &[T]) -> Option<T> {
+    current
+        .filter(|crtc| possible.contains(crtc))
+        .into_iter()
+        .chain(possible.iter().copied())
+        .find(|crtc| !taken.contains(crtc))
+}
+
+fn find_crtc(
+    card: &Card,
+    resources: &ResourceHandles,
+    info: &connector::Info,
+    taken: &[crtc::Handle],
+) -> Option<crtc::Handle> {
+    let encoders = info
+        .current_encoder()
+        .into_iter()
+        .chain(info.encoders().iter().copied());
+    encoders
+        .filter_map(|handle| card.get_encoder(handle).ok())
+        .find_map(|encoder| {
+            let possible = resources.filter_crtcs(encoder.possible_crtcs());
+            pick_crtc(encoder.crtc(), &possible, taken)
+        })
+}
+
+/// Picks every connected connector, with its mode and a CRTC of its own,
+/// the primary one first. Outputs left without a free CRTC are skipped.
+pub fn select_all(card: &Card, choice: &OutputChoice) -> Result<Vec<Display>, DisplayError> {
+    let resources = card.resource_handles()?;
+    let connectors: Vec<connector::Info> = resources
+        .connectors()
+        .iter()
+        .filter_map(|&handle| card.get_connector(handle, false).ok())
+        .collect();
+    let states: Vec<_> = connectors
+        .iter()
+        .map(|info| {
+            let connected = info.state() == connector::State::Connected;
+            (connector_name(info), connected && !info.modes().is_empty())
+        })
+        .collect();
+    let primary =
+        pick_connector(&states, choice.connector.as_deref()).ok_or(DisplayError::NoConnector)?;
+    let others = (0..states.len()).filter(|&i| i != primary && states[i].1);
+    let mut displays: Vec<Display> = Vec::new();
+    for index in std::iter::once(primary).chain(others) {
+        let (info, name) = (&connectors[index], states[index].0.clone());
+        let resolution = choice.resolution.filter(|_| index == primary);
+        let modes: Vec<ModeInfo> = info.modes().iter().map(ModeInfo::from).collect();
+        let Some(mode) = pick_mode(&modes, resolution).map(|i| info.modes()[i]) else {
+            eprintln!("{}", DisplayError::NoMode(name));
+            continue;
+        };
+        let taken: Vec<_> = displays.iter().map(|d| d.crtc).collect();
+        let Some(crtc) = find_crtc(card, &resources, info, &taken) else {
+            let err = DisplayError::NoCrtc(name);
+            if index == primary {
+                return Err(err);
+            }
+            eprintln!("{err}");
+            continue;
+        };
+        displays.push(Display {
+            connector: info.handle(),
+            name,
+            mode,
+            crtc,
+        });
+    }
+    Ok(displays)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode(width: u32, height: u32, refresh: u32, preferred: bool) -> ModeInfo {
+        ModeInfo {
+            size: (width, height),
+            refresh,
+            preferred,
+        }
+    }
+
+    fn card(name: &str, boot_vga: bool, connected: Option<usize>) -> CardInfo {
+        CardInfo {
+            name: name.to_string(),
+            boot_vga,
+            connected,
+        }
+    }
+
+    #[test]
+    fn cards_with_displays_win_over_boot_vga_and_order() {
+        let hybrid = [
+            card("card0", false, None),
+            card("card1", true, Some(0)),
+            card("card2", false, Some(1)),
+        ];
+        assert_eq!(pick_card(&hybrid), Some(2));
+        let desktop = [card("card0", false, Some(1)), card("card1", true, Some(2))];
+        assert_eq!(pick_card(&desktop), Some(1));
+        // Without any display plugged in yet, the first card that has
+        // connectors is used and waits for one.
+        let headless = [
+            card("card0", false, None),
+            card("card1", false, Some(0)),
+            card("card2", false, Some(0)),
+        ];
+        assert_eq!(pick_card(&headless), Some(1));
+        assert_eq!(pick_card(&[card("card0", true, None)]), None);
+        assert_eq!(headless[0].describe(), "render-only, skipped");
+        assert_eq!(desktop[1].describe(), "boot VGA, 2 connected displays");
+    }
+
+    #[test]
+    fn connected_connectors_are_picked_by_name_or_order() {
+        let connectors = [
+            ("DP-1".to_string(), false),
+            ("eDP-1".to_string(), true),
+            ("HDMI-A-1".to_string(), true),
+        ];
+        assert_eq!(pick_connector(&connectors, None), Some(1));
+        assert_eq!(pick_connector(&connectors, Some("hdmi-a-1")), Some(2));
+        // A disconnected or unknown name falls back to the first connected.
+        assert_eq!(pick_connector(&connectors, Some("DP-1")), Some(1));
+        assert_eq!(pick_connector(&connectors, Some("VGA-1")), Some(1));
+        assert_eq!(pick_connector(&connectors[..1], None), None);
+    }
+
+    #[test]
+    fn the_preferred_mode_wins_unless_a_resolution_is_asked_for() {
+        let modes = [
+            mode(3840, 2160, 30, false),
+            mode(2560, 1440, 60, true),
+            mode(1920, 1080, 60, true),
+            mode(1920, 1080, 120, false),
+        ];
+        assert_eq!(pick_mode(&modes, None), Some(1));
+        // The refresh rate beats the preferred flag among matching modes.
+        assert_eq!(pick_mode(&modes, Some((1920, 1080))), Some(3));
+        assert_eq!(pick_mode(&modes, Some((800, 600))), Some(1));
+        assert_eq!(pick_mode(&modes[2..], None), Some(0));
+        assert_eq!(pick_mode(&[], None), None);
+    }
+
+    #[test]
+    fn the_current_crtc_is_kept_when_possible() {
+        assert_eq!(pick_crtc(Some(2), &[1, 2], &[]), Some(2));
+        assert_eq!(pick_crtc(Some(3), &[1, 2], &[]), Some(1));
+        assert_eq!(pick_crtc(None, &[4], &[]), Some(4));
+        assert_eq!(pick_crtc::<u32>(None, &[], &[]), None);
+        // Outputs sharing an encoder's CRTCs each get their own.
+        assert_eq!(pick_crtc(Some(2), &[1, 2], &[2]), Some(1));
+        assert_eq!(pick_crtc(None, &[1, 2], &[1, 2]), None);
+    }
+}
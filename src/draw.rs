@@ -1,3 +1,6 @@
+use std::f64::consts::{FRAC_PI_2, PI};
+use std::path::Path;
+
 use crate::color::Color;
 use cairo::{Context as CairoContext, Format, ImageSurface};
 use pango::FontDescription;
@@ -11,104 +14,141 @@ pub enum DrawError {
     GlyphNotInCache(char),
     #[error("Cairo error: {0}")]
     Cairo(#[from] cairo::Error),
+    #[error("PNG error: {0}")]
+    Png(#[from] cairo::IoError),
+}
+
+/// Loads a PNG, or `None` if it is missing or unreadable; theme images are
+/// all optional.
+pub fn load_png(path: &Path) -> Option<ImageSurface> {
+    let mut file = std::fs::File::open(path).ok()?;
+    ImageSurface::create_from_png(&mut file).ok()
+}
+
+/// Renders `text` into an image just large enough to hold it.
+pub fn render_text(
+    text: &str,
+    font: &FontDescription,
+    color: &Color,
+) -> Result<ImageSurface, DrawError> {
+    let measure = CairoContext::new(&ImageSurface::create(Format::ARgb32, 1, 1)?)?;
+    let layout = create_layout(&measure);
+    layout.set_text(text);
+    layout.set_font_description(Some(font));
+    let (width, height) = layout.pixel_size();
+    let surface = ImageSurface::create(Format::ARgb32, width.max(1), height.max(1))?;
+    let context = CairoContext::new(&surface)?;
+    context.set_source_rgba(
+        color.red as f64,
+        color.green as f64,
+        color.blue as f64,
+        color.opacity as f64,
+    );
+    let layout = create_layout(&context);
+    layout.set_text(text);
+    layout.set_font_description(Some(font));
+    show_layout(&context, &layout);
+    drop(context);
+    Ok(surface)
 }
 
 pub struct FramebufferSurface {
     context: CairoContext,
-    // Double buffer for input region
-    region_surface: Option<ImageSurface>, // input field region
-    region_context: Option<CairoContext>,
-    region_dimensions: Option<(i32, i32, i32, i32)>, // x, y, width, height
 }
 
 impl FramebufferSurface {
-    pub fn new(framebuffer: &mut [u8], dimensions: (u32, u32)) -> Result<Self, DrawError> {
+    /// Draws into `framebuffer`, whose rows are `stride` bytes apart.
+    pub fn new(
+        framebuffer: &mut [u8],
+        dimensions: (u32, u32),
+        stride: u32,
+    ) -> Result<Self, DrawError> {
         let width = dimensions.0 as i32;
         let height = dimensions.1 as i32;
-        let stride = width * 4;
         let surface = ImageSurface::create_for_data(
-            unsafe {
-                std::slice::from_raw_parts_mut(framebuffer.as_mut_ptr(), (stride * height) as usize)
-            },
+            unsafe { std::slice::from_raw_parts_mut(framebuffer.as_mut_ptr(), framebuffer.len()) },
             Format::ARgb32,
             width,
             height,
-            stride,
+            stride as i32,
         )?;
         let context = CairoContext::new(&surface).unwrap();
-        Ok(Self {
-            context,
-            region_surface: None,
-            region_context: None,
-            region_dimensions: None,
-        })
+        Ok(Self { context })
     }
 
-    pub fn fill_rect(&self, x: i32, y: i32, width: i32, height: i32, color: &Color) {
-        self.context.set_source_rgba(
-            color.red as f64,
-            color.green as f64,
-            color.blue as f64,
-            color.opacity as f64,
+    pub fn draw_image(&self, image: &ImageSurface, x: i32, y: i32) {
+        let _ = self.context.set_source_surface(image, x as f64, y as f64);
+        let _ = self.context.paint();
+    }
+
+    pub fn draw_image_with_opacity(&self, image: &ImageSurface, x: i32, y: i32, opacity: f64) {
+        let _ = self.context.set_source_surface(image, x as f64, y as f64);
+        let _ = self.context.paint_with_alpha(opacity);
+    }
+
+    /// Draws `image` stretched to `width` by `height`.
+    pub fn draw_image_scaled(&self, image: &ImageSurface, x: i32, y: i32, width: i32, height: i32) {
+        if image.width() == 0 || image.height() == 0 {
+            return;
+        }
+        let _ = self.context.save();
+        self.context.translate(x as f64, y as f64);
+        self.context.scale(
+            width as f64 / image.width() as f64,
+            height as f64 / image.height() as f64,
         );
-        self.context
-            .rectangle(x as f64, y as f64, width as f64, height as f64);
-        let _ = self.context.fill();
+        let _ = self.context.set_source_surface(image, 0.0, 0.0);
+        let _ = self.context.paint();
+        let _ = self.context.restore();
     }
 
-    // Region fill for input fields
-    pub fn fill_input_region(&mut self, x: i32, y: i32, width: i32, height: i32, color: &Color) {
-        // Create region surface/context if not already
-        // Always clear input region before drawing new frame
-        let region_surf =
-            ImageSurface::create(Format::ARgb32, width, height).expect("failed region surf");
-        let region_ctx = CairoContext::new(&region_surf).unwrap();
-        self.region_surface = Some(region_surf);
-        self.region_context = Some(region_ctx);
-        self.region_dimensions = Some((x, y, width, height));
-        let ctx = self.region_context.as_mut().unwrap();
+    /// Fills a rectangle whose corners are rounded by `radius` pixels.
+    pub fn fill_rounded_rect(
+        &self,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        radius: f64,
+        color: &Color,
+    ) {
+        let (x, y, w, h) = (x as f64, y as f64, width as f64, height as f64);
+        let r = radius.clamp(0.0, w.min(h) / 2.0);
+        let ctx = &self.context;
+        ctx.new_sub_path();
+        ctx.arc(x + w - r, y + r, r, -FRAC_PI_2, 0.0);
+        ctx.arc(x + w - r, y + h - r, r, 0.0, FRAC_PI_2);
+        ctx.arc(x + r, y + h - r, r, FRAC_PI_2, PI);
+        ctx.arc(x + r, y + r, r, PI, 3.0 * FRAC_PI_2);
+        ctx.close_path();
         ctx.set_source_rgba(
             color.red as f64,
             color.green as f64,
             color.blue as f64,
             color.opacity as f64,
         );
-        ctx.rectangle(0.0, 0.0, width as f64, height as f64);
         let _ = ctx.fill();
     }
 
-    pub fn draw_text_region(
-        &mut self,
-        text: &str,
-        font: &FontDescription,
-        color: &Color,
-        y_offset: i32,
-    ) {
-        if let Some(ctx) = self.region_context.as_mut() {
-            ctx.set_source_rgba(
-                color.red as f64,
-                color.green as f64,
-                color.blue as f64,
-                color.opacity as f64,
-            );
-            let layout = create_layout(ctx);
-            layout.set_text(text);
-            layout.set_font_description(Some(font));
-            ctx.move_to(0.0, y_offset as f64);
-            show_layout(ctx, &layout);
-        }
+    /// Pixel size `text` takes up when drawn with `font`.
+    pub fn text_size(&self, text: &str, font: &FontDescription) -> (i32, i32) {
+        let layout = create_layout(&self.context);
+        layout.set_text(text);
+        layout.set_font_description(Some(font));
+        layout.pixel_size()
     }
 
-    pub fn composite_region_to_fb(&mut self) {
-        if let (Some(region_surf), Some((x, y, w, h))) =
-            (self.region_surface.as_ref(), self.region_dimensions)
-        {
-            let _ = self
-                .context
-                .set_source_surface(region_surf, x as f64, y as f64);
-            self.context
-                .rectangle(x as f64, y as f64, w as f64, h as f64);
-            let _ = self.context.fill();
-        }
+    pub fn draw_text(&self, text: &str, font: &FontDescription, color: &Color, x: i32, y: i32) {
+        self.context.set_source_rgba(
+            color.red as f64,
+            color.green as f64,
+            color.blue as f64,
+            color.opacity as f64,
+        );
+        let layout = create_layout(&self.context);
+        layout.set_text(text);
+        layout.set_font_description(Some(font));
+        self.context.move_to(x as f64, y as f64);
+        show_layout(&self.context, &layout);
     }
 }
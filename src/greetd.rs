@@ -1,91 +1,290 @@
 use std::env;
-use std::error::Error;
-use std::fmt;
 use std::os::unix::net::UnixStream;
-use std::process;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
 
 use greetd_ipc::{AuthMessageType, Request, Response, codec::SyncCodec};
+use thiserror::Error;
 
-#[derive(Debug)]
-struct LoginError(String);
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum GreetdError {
+    #[error("GREETD_SOCK must be defined")]
+    SocketMissing,
+    #[error("Could not connect to greetd: {0}")]
+    Connect(#[source] std::io::Error),
+    #[error("greetd protocol error: {0}")]
+    Protocol(#[from] greetd_ipc::codec::Error),
+    #[error("{0}")]
+    Auth(String),
+    #[error("Could not start session: {0}")]
+    StartSession(String),
+}
+
+impl GreetdError {
+    /// Whether the connection is unusable and has to be re-established.
+    pub fn is_connection_lost(&self) -> bool {
+        matches!(
+            self,
+            GreetdError::SocketMissing | GreetdError::Connect(_) | GreetdError::Protocol(_)
+        )
+    }
+}
+
+pub type AuthResult<T> = Result<T, GreetdError>;
+
+/// A message relayed by greetd from the PAM conversation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthPrompt {
+    /// Question whose answer may be echoed, e.g. a username or OTP.
+    Visible(String),
+    /// Question whose answer must be masked, e.g. a password.
+    Secret(String),
+    /// Informational text, acknowledged without an answer.
+    Info(String),
+    /// Error text, acknowledged without an answer.
+    Error(String),
+}
 
-impl fmt::Display for LoginError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "There is an error: {}", self.0)
+impl AuthPrompt {
+    pub fn text(&self) -> &str {
+        match self {
+            AuthPrompt::Visible(t)
+            | AuthPrompt::Secret(t)
+            | AuthPrompt::Info(t)
+            | AuthPrompt::Error(t) => t,
+        }
+    }
+
+    /// Whether greetd expects the user to type something for this message.
+    pub fn expects_answer(&self) -> bool {
+        matches!(self, AuthPrompt::Visible(_) | AuthPrompt::Secret(_))
     }
 }
 
-impl Error for LoginError {}
+/// State of the conversation after greetd answered a request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthStep {
+    Prompt(AuthPrompt),
+    Authenticated,
+}
 
 pub struct GreetD {
     pub stream: UnixStream,
 }
 
 impl GreetD {
-    pub fn new() -> Self {
-        let socket = env::var("GREETD_SOCK");
-        if socket.is_err() {
-            eprintln!("GREETD_SOCK must be defined");
-            process::exit(1);
-        }
-        match UnixStream::connect(socket.unwrap()) {
-            Ok(stream) => GreetD { stream },
+    /// Connects to the greetd instance named by `GREETD_SOCK`.
+    pub fn new() -> AuthResult<Self> {
+        let socket = env::var_os("GREETD_SOCK").ok_or(GreetdError::SocketMissing)?;
+        Self::connect(socket)
+    }
+
+    pub fn connect(socket: impl AsRef<Path>) -> AuthResult<Self> {
+        Ok(GreetD {
+            stream: UnixStream::connect(socket).map_err(GreetdError::Connect)?,
+        })
+    }
+
+    /// Starts a PAM conversation for `username` and returns its first step.
+    pub fn create_session(&mut self, username: String) -> AuthResult<AuthStep> {
+        Request::CreateSession { username }.write_to(&mut self.stream)?;
+        self.read_step()
+    }
 
-            Err(err) => {
-                eprintln!("{}", err);
-                process::exit(1);
+    /// Answers the pending message; `None` acknowledges `Info` and `Error`.
+    pub fn respond(&mut self, response: Option<String>) -> AuthResult<AuthStep> {
+        Request::PostAuthMessageResponse { response }.write_to(&mut self.stream)?;
+        self.read_step()
+    }
+
+    pub fn start_session(&mut self, cmd: Vec<String>, env: Vec<String>) -> AuthResult<()> {
+        Request::StartSession { cmd, env }.write_to(&mut self.stream)?;
+        match Response::read_from(&mut self.stream)? {
+            Response::Success => Ok(()),
+            Response::Error { description, .. } => Err(GreetdError::StartSession(description)),
+            Response::AuthMessage { .. } => {
+                Err(GreetdError::StartSession("unexpected auth message".into()))
             }
         }
     }
 
-    pub fn login(
-        &mut self,
-        username: String,
-        password: String,
-        cmd: Vec<String>,
-    ) -> Result<(), Box<dyn Error>> {
-        let _ = Request::CreateSession { username }.write_to(&mut self.stream);
-        let _ = Request::PostAuthMessageResponse {
-            response: Some(password),
-        }
-        .write_to(&mut self.stream);
-        let response = Response::read_from(&mut self.stream)?;
-        match response {
+    fn read_step(&mut self) -> AuthResult<AuthStep> {
+        match Response::read_from(&mut self.stream)? {
+            Response::Success => Ok(AuthStep::Authenticated),
+            Response::Error { description, .. } => Err(GreetdError::Auth(description)),
             Response::AuthMessage {
-                auth_message: _,
+                auth_message,
                 auth_message_type,
-            } => match auth_message_type {
-                AuthMessageType::Secret => {
-                    let _ = Request::StartSession {
-                        cmd,
-                        env: Vec::new(),
+            } => Ok(AuthStep::Prompt(match auth_message_type {
+                AuthMessageType::Visible => AuthPrompt::Visible(auth_message),
+                AuthMessageType::Secret => AuthPrompt::Secret(auth_message),
+                AuthMessageType::Info => AuthPrompt::Info(auth_message),
+                AuthMessageType::Error => AuthPrompt::Error(auth_message),
+            })),
+        }
+    }
+
+    /// Aborts the current conversation; greetd answers an error if there is
+    /// none, which is not a problem for us.
+    pub fn cancel(&mut self) -> AuthResult<()> {
+        Request::CancelSession.write_to(&mut self.stream)?;
+        Response::read_from(&mut self.stream)?;
+        Ok(())
+    }
+}
+
+/// Work handed to the [`AuthWorker`] thread.
+pub enum AuthRequest {
+    CreateSession(String),
+    Respond(Option<String>),
+    /// Command and `KEY=VALUE` environment of the session to start.
+    StartSession(Vec<String>, Vec<String>),
+    Cancel,
+}
+
+/// Outcome of an [`AuthRequest`], delivered in the order requests were sent.
+pub enum AuthReply {
+    Step(AuthResult<AuthStep>),
+    SessionStarted(AuthResult<()>),
+    Cancelled(AuthResult<()>),
+}
+
+/// Runs the blocking greetd conversation on its own thread, so PAM's fail
+/// delay doesn't stall drawing and input.
+pub struct AuthWorker {
+    requests: mpsc::Sender<AuthRequest>,
+    replies: mpsc::Receiver<AuthReply>,
+}
+
+impl AuthWorker {
+    pub fn spawn(mut greetd: GreetD) -> Self {
+        let (requests, pending) = mpsc::channel();
+        let (done, replies) = mpsc::channel();
+        thread::spawn(move || {
+            for request in pending {
+                let reply = match request {
+                    AuthRequest::CreateSession(username) => {
+                        AuthReply::Step(greetd.create_session(username))
                     }
-                    .write_to(&mut self.stream);
-                    let resp = Response::read_from(&mut self.stream)?;
-                    match resp {
-                        Response::Success => Ok(()),
-                        Response::Error { .. } | Response::AuthMessage { .. } => {
-                            Err(Box::new(LoginError("Wrong username or password".into())))
-                        }
+                    AuthRequest::Respond(response) => AuthReply::Step(greetd.respond(response)),
+                    AuthRequest::StartSession(cmd, env) => {
+                        AuthReply::SessionStarted(greetd.start_session(cmd, env))
                     }
+                    AuthRequest::Cancel => AuthReply::Cancelled(greetd.cancel()),
+                };
+                if done.send(reply).is_err() {
+                    break;
                 }
-                _ => Err(Box::new(LoginError("Wrong username".into()))),
-            },
-            Response::Success => {
-                let _ = Request::StartSession {
-                    cmd,
-                    env: Vec::new(),
-                }
-                .write_to(&mut self.stream);
-                let _ = Response::read_from(&mut self.stream)?;
-                Ok(())
             }
-            _ => Err(Box::new(LoginError("Unknown error".into()))),
-        }
+        });
+        Self { requests, replies }
+    }
+
+    pub fn send(&self, request: AuthRequest) {
+        let _ = self.requests.send(request);
+    }
+
+    pub fn recv_timeout(&self, timeout: std::time::Duration) -> Option<AuthReply> {
+        self.replies.recv_timeout(timeout).ok()
     }
 
-    pub fn cancel(&mut self) {
-        let _ = Request::CancelSession.write_to(&mut self.stream);
-        let _ = Response::read_from(&mut self.stream);
+    /// Returns the next finished reply without blocking.
+    pub fn try_recv(&self) -> Option<AuthReply> {
+        self.replies.try_recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_greetd::{MockGreetd, script};
+
+    const CMD: &[&str] = &["sway"];
+
+    fn cmd() -> Vec<String> {
+        CMD.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn password_login_starts_session() {
+        let server = MockGreetd::spawn(script::success(CMD));
+        let mut greetd = GreetD::connect(server.path()).unwrap();
+        assert_eq!(
+            greetd.create_session("alice".into()).unwrap(),
+            AuthStep::Prompt(AuthPrompt::Secret("Password: ".into()))
+        );
+        assert_eq!(
+            greetd.respond(Some("hunter2".into())).unwrap(),
+            AuthStep::Authenticated
+        );
+        greetd.start_session(cmd(), Vec::new()).unwrap();
+        drop(greetd);
+        server.finish().unwrap();
+    }
+
+    #[test]
+    fn wrong_password_reports_description() {
+        let server = MockGreetd::spawn(script::wrong_password());
+        let mut greetd = GreetD::connect(server.path()).unwrap();
+        greetd.create_session("alice".into()).unwrap();
+        let err = greetd.respond(Some("hunter2".into())).unwrap_err();
+        assert!(matches!(err, GreetdError::Auth(ref d) if d == "Authentication failed"));
+        greetd.cancel().unwrap();
+        drop(greetd);
+        server.finish().unwrap();
+    }
+
+    #[test]
+    fn follow_up_prompt_keeps_its_echo_mode() {
+        let server = MockGreetd::spawn(script::multi_prompt(CMD));
+        let mut greetd = GreetD::connect(server.path()).unwrap();
+        greetd.create_session("alice".into()).unwrap();
+        assert_eq!(
+            greetd.respond(Some("hunter2".into())).unwrap(),
+            AuthStep::Prompt(AuthPrompt::Visible("Verification code: ".into()))
+        );
+        assert_eq!(
+            greetd.respond(Some("123456".into())).unwrap(),
+            AuthStep::Authenticated
+        );
+        greetd.start_session(cmd(), Vec::new()).unwrap();
+        drop(greetd);
+        server.finish().unwrap();
+    }
+
+    #[test]
+    fn missing_socket_is_a_connect_error() {
+        let path = std::env::temp_dir().join("ndlm-greetd-missing.sock");
+        let err = GreetD::connect(path).err().unwrap();
+        assert!(matches!(err, GreetdError::Connect(_)));
+        assert!(err.is_connection_lost());
+    }
+
+    #[test]
+    fn start_session_failure_is_an_error() {
+        let server = MockGreetd::spawn(script::start_session_failure(CMD));
+        let worker = AuthWorker::spawn(GreetD::connect(server.path()).unwrap());
+        worker.send(AuthRequest::CreateSession("alice".into()));
+        worker.send(AuthRequest::Respond(Some("hunter2".into())));
+        worker.send(AuthRequest::StartSession(cmd(), Vec::new()));
+        let timeout = std::time::Duration::from_secs(5);
+        assert!(matches!(
+            worker.recv_timeout(timeout),
+            Some(AuthReply::Step(Ok(_)))
+        ));
+        assert!(matches!(
+            worker.recv_timeout(timeout),
+            Some(AuthReply::Step(Ok(AuthStep::Authenticated)))
+        ));
+        match worker.recv_timeout(timeout) {
+            Some(AuthReply::SessionStarted(Err(GreetdError::StartSession(description)))) => {
+                assert_eq!(description, "command not found")
+            }
+            _ => panic!("expected a failed session start"),
+        }
+        drop(worker);
+        server.finish().unwrap();
     }
 }
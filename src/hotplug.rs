@@ -0,0 +1,163 @@
+//! Display and input hotplug notifications from the kernel's uevent
+//! netlink socket.
+
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use crate::output::Output;
+
+/// Multicast group the kernel sends uevents to, as opposed to udev's.
+const KERNEL_UEVENTS: u32 = 1;
+
+/// Where uevents come from, so hotplug handling can be fed synthetic ones.
+pub trait UeventSource {
+    /// Descriptor that becomes readable when an event arrives.
+    fn fd(&self) -> Option<RawFd>;
+    /// The next pending event, without blocking.
+    fn next_event(&mut self) -> Option<Vec<u8>>;
+}
+
+/// Kernel uevents read from a `NETLINK_KOBJECT_UEVENT` socket.
+pub struct NetlinkUevents {
+    socket: OwnedFd,
+}
+
+impl NetlinkUevents {
+    pub fn open() -> std::io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::NETLINK_KOBJECT_UEVENT,
+            )
+        };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = KERNEL_UEVENTS;
+        let res = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                (&addr as *const libc::sockaddr_nl).cast(),
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self { socket })
+    }
+}
+
+impl UeventSource for NetlinkUevents {
+    fn fd(&self) -> Option<RawFd> {
+        Some(self.socket.as_raw_fd())
+    }
+
+    fn next_event(&mut self) -> Option<Vec<u8>> {
+        let mut buf = vec![0u8; 8192];
+        let n = unsafe {
+            libc::recv(
+                self.socket.as_raw_fd(),
+                buf.as_mut_ptr().cast(),
+                buf.len(),
+                0,
+            )
+        };
+        if n <= 0 {
+            return None;
+        }
+        buf.truncate(n as usize);
+        Some(buf)
+    }
+}
+
+/// The `KEY=VALUE` fields of a uevent, `ACTION@DEVPATH` followed by NUL
+/// separated fields.
+fn fields(event: &[u8]) -> Vec<(&str, &str)> {
+    event
+        .split(|&b| b == 0)
+        .filter_map(|field| std::str::from_utf8(field).ok()?.split_once('='))
+        .collect()
+}
+
+/// Whether a uevent says the displays on a DRM card changed.
+pub fn is_drm_hotplug(event: &[u8]) -> bool {
+    let fields = fields(event);
+    let has = |key: &str, value: &str| fields.contains(&(key, value));
+    has("SUBSYSTEM", "drm") && has("HOTPLUG", "1")
+}
+
+/// Whether a uevent says an evdev node, such as a mouse's, was added or
+/// removed.
+pub fn is_input_hotplug(event: &[u8]) -> bool {
+    let fields = fields(event);
+    let has = |key: &str, value: &str| fields.contains(&(key, value));
+    let event_node = fields
+        .iter()
+        .any(|&(key, value)| key == "DEVNAME" && value.starts_with("input/event"));
+    has("SUBSYSTEM", "input") && (has("ACTION", "add") || has("ACTION", "remove")) && event_node
+}
+
+/// What a batch of uevents changed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Changes {
+    /// Displays were plugged in or removed.
+    pub displays: bool,
+    /// Input devices were plugged in or removed.
+    pub input: bool,
+}
+
+/// Reads every pending event and sums up what they changed.
+pub fn drain(source: &mut dyn UeventSource) -> Changes {
+    let mut changes = Changes::default();
+    while let Some(event) = source.next_event() {
+        changes.displays |= is_drm_hotplug(&event);
+        changes.input |= is_input_hotplug(&event);
+    }
+    changes
+}
+
+/// Hotplug events and how to set the outputs up again after a display one.
+pub struct Hotplug<'a> {
+    pub events: Box<dyn UeventSource + 'a>,
+    /// Probes the connectors and returns an output for each display, the
+    /// primary one first.
+    pub reprobe: Box<dyn FnMut() -> Vec<Box<dyn Output + 'a>> + 'a>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_drm_hotplug_events_count() {
+        let hotplug = b"change@/devices/pci0000:00/0000:00:02.0/drm/card0\0ACTION=change\0\
+            DEVPATH=/devices/pci0000:00/0000:00:02.0/drm/card0\0SUBSYSTEM=drm\0HOTPLUG=1\0\
+            DEVNAME=/dev/dri/card0\0DEVTYPE=drm_minor\0SEQNUM=4242\0";
+        assert!(is_drm_hotplug(hotplug));
+        let usb = b"add@/devices/usb1/1-1\0ACTION=add\0SUBSYSTEM=usb\0HOTPLUG=1\0";
+        assert!(!is_drm_hotplug(usb));
+        let backlight = b"change@/devices/drm/card0-eDP-1\0ACTION=change\0SUBSYSTEM=drm\0";
+        assert!(!is_drm_hotplug(backlight));
+        assert!(!is_drm_hotplug(b""));
+    }
+
+    #[test]
+    fn only_evdev_nodes_count_as_input_hotplug() {
+        let mouse = b"add@/devices/usb1/1-1/input/input12/event5\0ACTION=add\0\
+            SUBSYSTEM=input\0MAJOR=13\0MINOR=69\0DEVNAME=input/event5\0SEQNUM=4243\0";
+        assert!(is_input_hotplug(mouse));
+        assert!(!is_drm_hotplug(mouse));
+        let unplugged = b"remove@/devices/usb1/1-1/input/input12/event5\0ACTION=remove\0\
+            SUBSYSTEM=input\0DEVNAME=input/event5\0";
+        assert!(is_input_hotplug(unplugged));
+        let parent = b"add@/devices/usb1/1-1/input/input12\0ACTION=add\0SUBSYSTEM=input\0";
+        assert!(!is_input_hotplug(parent));
+        let joystick = b"add@/devices/usb1/1-2/input/input13/js0\0ACTION=add\0\
+            SUBSYSTEM=input\0DEVNAME=input/js0\0";
+        assert!(!is_input_hotplug(joystick));
+    }
+}
@@ -0,0 +1,416 @@
+//! Widget layout of the login screen.
+//!
+//! Native `.toml` themes declare their widgets; plymouth themes get the
+//! layout ndlm has always drawn for them from [`Layout::plymouth`].
+
+use std::ffi::CString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use pango::FontDescription;
+use serde::Deserialize;
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+
+use crate::color::Color;
+use crate::theme::{DEFAULT_FONT, Module, font_with_fallback};
+
+const DEFAULT_CLOCK_FORMAT: &str = "%H:%M";
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WidgetKind {
+    /// A filled panel, by default as large as the screen.
+    Background,
+    Title,
+    Clock,
+    Username,
+    Password,
+    Session,
+    Message,
+    /// Lists the power keys; F1 powers off and F2 reboots, each confirmed
+    /// by a second press.
+    Power,
+    Image,
+}
+
+impl WidgetKind {
+    /// Widgets of the built-in prompt, which a two-step or script theme
+    /// draws in its own way.
+    pub fn is_prompt(self) -> bool {
+        matches!(
+            self,
+            WidgetKind::Username | WidgetKind::Password | WidgetKind::Session | WidgetKind::Message
+        )
+    }
+}
+
+/// A point given as fractions of the width and height of the screen or a
+/// widget, so `(0.5, 1.0)` is the middle of the bottom edge.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Anchor {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Anchor {
+    pub const fn new(x: f32, y: f32) -> Self {
+        Anchor { x, y }
+    }
+}
+
+impl FromStr for Anchor {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (x, y) = match s.trim().to_lowercase().as_str() {
+            "top-left" => (0.0, 0.0),
+            "top" => (0.5, 0.0),
+            "top-right" => (1.0, 0.0),
+            "left" => (0.0, 0.5),
+            "center" => (0.5, 0.5),
+            "right" => (1.0, 0.5),
+            "bottom-left" => (0.0, 1.0),
+            "bottom" => (0.5, 1.0),
+            "bottom-right" => (1.0, 1.0),
+            _ => {
+                return Err(format!(
+                    "{s:?} is not an anchor such as \"center\" or [0.5, 0.3]"
+                ));
+            }
+        };
+        Ok(Anchor::new(x, y))
+    }
+}
+
+/// Anchors are written as a name such as `"bottom-right"` or as an
+/// `[x, y]` pair of fractions.
+impl<'de> Deserialize<'de> for Anchor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AnchorVisitor;
+
+        impl<'de> Visitor<'de> for AnchorVisitor {
+            type Value = Anchor;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("an anchor name or an [x, y] pair")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Anchor, E> {
+                value.parse().map_err(E::custom)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Anchor, A::Error> {
+                let x = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let y = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                if seq.next_element::<f32>()?.is_some() {
+                    return Err(de::Error::invalid_length(3, &self));
+                }
+                Ok(Anchor::new(x, y))
+            }
+        }
+
+        deserializer.deserialize_any(AnchorVisitor)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+fn font<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<FontDescription>, D::Error> {
+    let font = Option::<String>::deserialize(deserializer)?;
+    Ok(font.map(|font| font_with_fallback(&font, DEFAULT_FONT)))
+}
+
+/// One `[[widget]]` of a theme.
+///
+/// A widget is placed by putting its `pivot` on the screen's `anchor` and
+/// moving it by `x` and `y` pixels. Unset fonts and colors come from the
+/// theme's style.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Widget {
+    #[serde(rename = "type")]
+    pub kind: WidgetKind,
+    #[serde(default)]
+    pub anchor: Anchor,
+    /// Defaults to `anchor`, which keeps e.g. a `bottom-right` widget
+    /// inside the bottom right corner.
+    pub pivot: Option<Anchor>,
+    #[serde(default)]
+    pub x: i32,
+    #[serde(default)]
+    pub y: i32,
+    /// Fixed size; unset, the widget is as large as its content and padding.
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    #[serde(default)]
+    pub padding: i32,
+    #[serde(default, deserialize_with = "font")]
+    pub font: Option<FontDescription>,
+    pub color: Option<Color>,
+    /// Text color of a field while it is being typed into.
+    pub focused_color: Option<Color>,
+    /// Fill behind the widget.
+    pub background: Option<Color>,
+    #[serde(default)]
+    pub radius: f64,
+    /// Widgets are drawn by increasing `z`; below zero they go under the
+    /// boot animation and script sprites. Unset, background panels are at
+    /// -1 and everything else at 0.
+    pub z: Option<i32>,
+    #[serde(default)]
+    pub align: TextAlign,
+    /// Label of a field, or the text of a title or power menu.
+    pub text: Option<String>,
+    /// `strftime` format of a clock.
+    pub format: Option<String>,
+    /// PNG of an image widget, relative to the theme file.
+    pub path: Option<PathBuf>,
+}
+
+impl Widget {
+    fn new(kind: WidgetKind, anchor: Anchor) -> Self {
+        Widget {
+            kind,
+            anchor,
+            pivot: None,
+            x: 0,
+            y: 0,
+            width: None,
+            height: None,
+            padding: 0,
+            font: None,
+            color: None,
+            focused_color: None,
+            background: None,
+            radius: 0.0,
+            z: None,
+            align: TextAlign::default(),
+            text: None,
+            format: None,
+            path: None,
+        }
+    }
+
+    pub fn z(&self) -> i32 {
+        self.z.unwrap_or(match self.kind {
+            WidgetKind::Background => -1,
+            _ => 0,
+        })
+    }
+
+    /// The `strftime` format of a clock widget.
+    pub fn clock_format(&self) -> &str {
+        self.format.as_deref().unwrap_or(DEFAULT_CLOCK_FORMAT)
+    }
+
+    /// Size of the widget around content of `content` size.
+    pub fn size(&self, content: (i32, i32)) -> (i32, i32) {
+        (
+            self.width.unwrap_or(content.0 + 2 * self.padding),
+            self.height.unwrap_or(content.1 + 2 * self.padding),
+        )
+    }
+
+    /// Offset of `text_width` wide text from the widget's left edge in a
+    /// widget `width` wide. Text wider than the widget starts at its left
+    /// padding whatever the alignment.
+    pub fn text_offset(&self, width: i32, text_width: i32) -> i32 {
+        let room = (width - 2 * self.padding - text_width).max(0);
+        self.padding
+            + match self.align {
+                TextAlign::Left => 0,
+                TextAlign::Center => room / 2,
+                TextAlign::Right => room,
+            }
+    }
+
+    /// Top-left corner of the widget at `size` on a `screen`-sized output,
+    /// kept on screen.
+    pub fn position(&self, size: (i32, i32), screen: (u32, u32)) -> (i32, i32) {
+        let pivot = self.pivot.unwrap_or(self.anchor);
+        let place = |screen: u32, size: i32, anchor: f32, pivot: f32, offset: i32| {
+            let at = (screen as f32 * anchor - size as f32 * pivot) as i32 + offset;
+            at.clamp(0, (screen as i32 - size).max(0))
+        };
+        (
+            place(screen.0, size.0, self.anchor.x, pivot.x, self.x),
+            place(screen.1, size.1, self.anchor.y, pivot.y, self.y),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Layout {
+    pub widgets: Vec<Widget>,
+}
+
+impl Layout {
+    /// The layout of a plymouth theme: watermark and title at their own
+    /// alignments, and the text prompt in rows below the dialog alignment.
+    pub fn plymouth(module: &Module) -> Self {
+        let mut widgets = Vec::new();
+        if !module.image_dir.is_empty() {
+            let mut watermark = Widget::new(
+                WidgetKind::Image,
+                Anchor::new(
+                    module.watermark_horizontal_alignment,
+                    module.watermark_vertical_alignment,
+                ),
+            );
+            watermark.path = Some(Path::new(&module.image_dir).join("watermark.png"));
+            watermark.z = Some(-1);
+            widgets.push(watermark);
+        }
+        let mut title = Widget::new(
+            WidgetKind::Title,
+            Anchor::new(
+                module.title_horizontal_alignment,
+                module.title_vertical_alignment,
+            ),
+        );
+        title.z = Some(-1);
+        widgets.push(title);
+
+        let dialog = Anchor::new(
+            module.dialog_horizontal_alignment,
+            module.dialog_vertical_alignment,
+        );
+        let rows = [
+            (WidgetKind::Username, -40),
+            (WidgetKind::Password, -16),
+            (WidgetKind::Message, 8),
+            (WidgetKind::Session, 26),
+        ];
+        for (kind, y) in rows {
+            let mut row = Widget::new(kind, dialog);
+            row.pivot = Some(Anchor::default());
+            (row.x, row.y) = (-120, y);
+            widgets.push(row);
+        }
+        Layout { widgets }
+    }
+
+    pub fn has(&self, kind: WidgetKind) -> bool {
+        self.widgets.iter().any(|w| w.kind == kind)
+    }
+
+    pub fn find(&self, kind: WidgetKind) -> Option<&Widget> {
+        self.widgets.iter().find(|w| w.kind == kind)
+    }
+
+    /// Indices of the widgets in drawing order.
+    pub fn z_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.widgets.len()).collect();
+        order.sort_by_key(|&i| self.widgets[i].z());
+        order
+    }
+}
+
+/// Formats the local time `time` (seconds since the epoch) with `strftime`.
+pub fn format_time(format: &str, time: i64) -> String {
+    let Ok(format) = CString::new(format) else {
+        return String::new();
+    };
+    let mut buf = [0u8; 256];
+    let len = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&(time as libc::time_t), &mut tm).is_null() {
+            return String::new();
+        }
+        libc::strftime(buf.as_mut_ptr().cast(), buf.len(), format.as_ptr(), &tm)
+    };
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// Whether a clock `format` shows seconds and so changes every second
+/// rather than every minute.
+pub fn shows_seconds(format: &str) -> bool {
+    ["%S", "%T", "%r", "%X", "%s", "%c"]
+        .iter()
+        .any(|spec| format.contains(spec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn widget(anchor: &str) -> Widget {
+        Widget::new(WidgetKind::Clock, anchor.parse().unwrap())
+    }
+
+    #[test]
+    fn widgets_are_placed_by_anchor_and_pivot() {
+        let screen = (800, 600);
+        assert_eq!(widget("center").position((100, 40), screen), (350, 280));
+        let mut corner = widget("bottom-right");
+        (corner.x, corner.y) = (-20, -10);
+        assert_eq!(corner.position((100, 40), screen), (680, 550));
+        corner.pivot = Some(Anchor::default());
+        assert_eq!(corner.position((100, 40), screen), (700, 560));
+        // Offsets never push a widget off the screen.
+        corner.x = 500;
+        assert_eq!(corner.position((100, 40), screen), (700, 560));
+        assert!("middle".parse::<Anchor>().is_err());
+    }
+
+    #[test]
+    fn aligned_text_stays_inside_narrow_widgets() {
+        let mut label = widget("center");
+        (label.padding, label.align) = (4, TextAlign::Right);
+        assert_eq!(label.text_offset(100, 50), 46);
+        label.align = TextAlign::Center;
+        assert_eq!(label.text_offset(100, 50), 25);
+        assert_eq!(label.text_offset(40, 50), 4);
+    }
+
+    #[test]
+    fn background_panels_go_under_the_animation() {
+        let panel = Widget::new(WidgetKind::Background, Anchor::default());
+        let clock = widget("center");
+        assert_eq!((panel.z(), clock.z()), (-1, 0));
+        let layout = Layout {
+            widgets: vec![clock, panel],
+        };
+        assert_eq!(layout.z_order(), [1, 0]);
+    }
+
+    #[test]
+    fn plymouth_prompt_rows_keep_their_offsets() {
+        let module = Module {
+            dialog_horizontal_alignment: 0.5,
+            dialog_vertical_alignment: 0.5,
+            ..Module::default()
+        };
+        let layout = Layout::plymouth(&module);
+        let screen = (800, 450);
+        let rows: Vec<_> = [
+            WidgetKind::Username,
+            WidgetKind::Password,
+            WidgetKind::Message,
+            WidgetKind::Session,
+        ]
+        .map(|kind| layout.find(kind).unwrap().position((200, 20), screen))
+        .into();
+        assert_eq!(rows, [(280, 185), (280, 209), (280, 233), (280, 251)]);
+        assert!(!layout.has(WidgetKind::Image));
+    }
+
+    #[test]
+    fn clocks_format_local_time() {
+        // Mid-year, so the year is the same in every time zone.
+        assert_eq!(format_time("%Y", 1_183_000_000), "2007");
+        assert!(shows_seconds("%H:%M:%S"));
+        assert!(!shows_seconds("%H:%M"));
+    }
+}
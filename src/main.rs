@@ -1,18 +1,36 @@
 #![deny(rust_2018_idioms)]
 
-use std::fs;
-use std::str::FromStr;
+use std::path::PathBuf;
 
-use pango::FontDescription;
 use termion::raw::IntoRawMode;
 use thiserror::Error;
 
-use crate::{color::Color, manager::LoginManager};
+use crate::hotplug::{Hotplug, NetlinkUevents};
+use crate::manager::LoginManager;
+use crate::output::{Card, DrmOutput, HeadlessOutput, Output};
+use crate::theme::{PLYMOUTHD_CONFIGS, THEME_DIR, Theme};
 
+mod animation;
+mod assets;
+mod background;
 mod color;
+mod display;
 mod draw;
 mod greetd;
+mod hotplug;
+mod layout;
 mod manager;
+#[cfg(test)]
+mod mock_greetd;
+mod output;
+mod pointer;
+mod script;
+mod slideshow;
+mod theme;
+
+const DEFAULT_RENDER_SIZE: (u32, u32) = (1920, 1080);
+/// Cairo's limit on either side of a surface.
+const MAX_SIZE: u32 = 32767;
 
 #[derive(Error, Debug)]
 #[non_exhaustive]
@@ -21,125 +39,75 @@ pub enum Error {
     Draw(#[from] draw::DrawError),
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("greetd error: {0}")]
+    Greetd(#[from] greetd::GreetdError),
+    #[error("theme error: {0}")]
+    Theme(#[from] theme::ThemeError),
 }
 
-#[derive(Clone)]
-struct Module {
-    font: FontDescription,
-    title_font: FontDescription,
-    image_dir: String,
-    dialog_horizontal_alignment: f32,
-    dialog_vertical_alignment: f32,
-    title_horizontal_alignment: f32,
-    title_vertical_alignment: f32,
-    watermark_horizontal_alignment: f32,
-    watermark_vertical_alignment: f32,
-    horizontal_alignment: f32,
-    vertical_alignment: f32,
-    background_start_color: Color,
-    background_end_color: Color,
-}
-impl Default for Module {
-    fn default() -> Self {
-        Module {
-            font: FontDescription::from_string(""),
-            title_font: FontDescription::from_string(""),
-            image_dir: "".to_string(),
-            dialog_horizontal_alignment: 0f32,
-            dialog_vertical_alignment: 0f32,
-            title_horizontal_alignment: 0f32,
-            title_vertical_alignment: 0f32,
-            watermark_horizontal_alignment: 0f32,
-            watermark_vertical_alignment: 0f32,
-            horizontal_alignment: 0f32,
-            vertical_alignment: 0f32,
-            background_start_color: Color::default(),
-            background_end_color: Color::default(),
-        }
-    }
+#[derive(Default, Clone)]
+struct Config {
+    session: Vec<String>,
+    theme: Theme,
+    /// Extra `KEY=VALUE` variables for every session.
+    env: Vec<String>,
+    /// Extra `KEY=VALUE` variables keyed by session ID or name.
+    session_env: Vec<(String, String)>,
+    /// Render one frame to this PNG instead of driving a display.
+    render_to: Option<String>,
+    render_size: Option<(u32, u32)>,
+    /// Overrides the title text from the theme.
+    title: Option<String>,
+    /// Primary connector, its resolution and where the dialog goes.
+    output: display::OutputChoice,
 }
 
-impl FromStr for Module {
-    type Err = Error;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut module = Module::default();
-        for l in s.lines() {
-            if l.contains("=") {
-                let (key, value) = match &l.split("=").collect::<Vec<&str>>()[..] {
-                    &[first, second, ..] => (first, second),
-                    _ => unreachable!(),
-                };
-                let mut v = 0f32;
-                if value.starts_with(".") {
-                    v = format!("0{}", value).parse().unwrap();
-                }
-                match key {
-                    "Font" => module.font = FontDescription::from_string(value),
-                    "TitleFont" => module.title_font = FontDescription::from_string(value),
-                    "ImageDir" => module.image_dir = value.to_string(),
-                    "DialogHorizontalAlignment" => module.dialog_horizontal_alignment = v,
-                    "DialogVerticalAlignment" => module.dialog_vertical_alignment = v,
-                    "TitleHorizontalAlignment" => module.title_horizontal_alignment = v,
-                    "TitleVerticalAlignment" => module.title_vertical_alignment = v,
-                    "HorizontalAlignment" => module.horizontal_alignment = v,
-                    "VerticalAlignment" => module.vertical_alignment = v,
-                    "WatermarkHorizontalAlignment" => module.watermark_horizontal_alignment = v,
-                    "WatermarkVerticalAlignment" => module.watermark_vertical_alignment = v,
-                    "BackgroundStartColor" => {
-                        module.background_start_color = value.parse().unwrap()
-                    }
-                    "BackgroundEndColor" => module.background_end_color = value.parse().unwrap(),
-                    _ => {}
-                }
-            }
-        }
-        Ok(module)
-    }
+/// `WIDTHxHEIGHT`, each side from 1 to the largest cairo can draw.
+fn parse_size(value: &str) -> Option<(u32, u32)> {
+    let (width, height) = value.split_once('x')?;
+    let side = |text: &str| {
+        text.parse()
+            .ok()
+            .filter(|side| (1..=MAX_SIZE).contains(side))
+    };
+    Some((side(width)?, side(height)?))
 }
 
-#[derive(Default, Clone)]
-struct Theme {
-    name: String,
-    description: Option<String>,
-    module: Module,
+/// How the theme was chosen on the command line.
+enum ThemeChoice {
+    File(PathBuf),
+    Name(String),
 }
 
-impl FromStr for Theme {
-    type Err = Error;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut theme = Theme::default();
-        for l in s.lines() {
-            if l.contains("=") {
-                let (key, value) = match &l.split("=").collect::<Vec<&str>>()[..] {
-                    &[first, second, ..] => (first, second),
-                    _ => unreachable!(),
-                };
-                match key {
-                    "Name" => theme.name = value.to_string(),
-                    "Description" => theme.description = Some(value.to_string()),
-                    "ModuleName" => theme.module = s.parse().unwrap(),
-                    _ => {}
-                }
+/// Loads the chosen theme, or the one the machine boots with if none was
+/// given. A broken theme must not keep anyone from logging in, so failures
+/// fall back to the default theme.
+fn load_theme(choice: Option<ThemeChoice>, theme_dirs: &[PathBuf]) -> Theme {
+    let path = match choice {
+        Some(ThemeChoice::File(path)) => Ok(path),
+        Some(ThemeChoice::Name(name)) => theme::find_theme(&name, theme_dirs),
+        None => {
+            let configs = PLYMOUTHD_CONFIGS.map(PathBuf::from);
+            match theme::system_theme(&configs, theme_dirs) {
+                Some(path) => Ok(path),
+                None => return Theme::default(),
             }
         }
-        Ok(theme)
+    };
+    match path.and_then(|path| Theme::from_file(&path)) {
+        Ok(theme) => theme,
+        Err(err) => {
+            eprintln!("{err}, using the default theme");
+            Theme::default()
+        }
     }
 }
 
-#[derive(Default, Clone)]
-struct Config {
-    session: Vec<String>,
-    theme: Theme,
-}
-
-fn parse_theme(theme_file: String) -> Theme {
-    let content = fs::read_to_string(theme_file).expect("Unable to read theme file");
-    content.parse().unwrap()
-}
-
 fn parse_args() -> Config {
     let mut args = std::env::args().skip(1); // skip program name
     let mut config = Config::default();
+    let mut theme = None;
+    let mut theme_dirs = Vec::new();
 
     while let Some(arg) = args.next() {
         match arg.as_str() {
@@ -150,9 +118,76 @@ fn parse_args() -> Config {
                     eprintln!("Expected a value after --session");
                 }
             }
+            "--env" => match args.next() {
+                Some(value) if value.contains('=') => config.env.push(value),
+                _ => eprintln!("Expected KEY=VALUE after --env"),
+            },
+            "--session-env" => match (args.next(), args.next()) {
+                (Some(session), Some(value)) if value.contains('=') => {
+                    config.session_env.push((session, value))
+                }
+                _ => eprintln!("Expected a session and KEY=VALUE after --session-env"),
+            },
+            "--title" => {
+                if let Some(value) = args.next() {
+                    config.title = Some(value);
+                } else {
+                    eprintln!("Expected a value after --title");
+                }
+            }
+            "--render-to" => {
+                if let Some(value) = args.next() {
+                    config.render_to = Some(value);
+                } else {
+                    eprintln!("Expected a value after --render-to");
+                }
+            }
+            "--card" => {
+                if let Some(value) = args.next() {
+                    config.output.card = Some(value);
+                } else {
+                    eprintln!("Expected a card such as card1 after --card");
+                }
+            }
+            "--connector" => {
+                if let Some(value) = args.next() {
+                    config.output.connector = Some(value);
+                } else {
+                    eprintln!("Expected a connector name such as eDP-1 after --connector");
+                }
+            }
+            "--resolution" => match args.next().as_deref().and_then(parse_size) {
+                Some(size) => config.output.resolution = Some(size),
+                None => eprintln!("Expected WIDTHxHEIGHT after --resolution"),
+            },
+            "--dialog" => match args.next().map(|value| value.parse()) {
+                Some(Ok(placement)) => config.output.dialog = placement,
+                Some(Err(err)) => eprintln!("{err} after --dialog"),
+                None => eprintln!("Expected primary, pointer or mirror after --dialog"),
+            },
+            "--render-size" => match args.next().as_deref().and_then(parse_size) {
+                Some(size) => config.render_size = Some(size),
+                None => eprintln!(
+                    "Expected WIDTHxHEIGHT, each from 1 to {MAX_SIZE}, after --render-size"
+                ),
+            },
+            "--theme" => {
+                if let Some(value) = args.next() {
+                    theme = Some(ThemeChoice::Name(value));
+                } else {
+                    eprintln!("Expected a value after --theme");
+                }
+            }
+            "--theme-dir" => {
+                if let Some(value) = args.next() {
+                    theme_dirs.push(PathBuf::from(value));
+                } else {
+                    eprintln!("Expected a value after --theme-dir");
+                }
+            }
             "--theme-file" => {
                 if let Some(value) = args.next() {
-                    config.theme = parse_theme(value);
+                    theme = Some(ThemeChoice::File(PathBuf::from(value)));
                 } else {
                     eprintln!("Expected a value after --theme-file");
                 }
@@ -166,37 +201,54 @@ fn parse_args() -> Config {
         }
     }
 
+    theme_dirs.push(PathBuf::from(THEME_DIR));
+    config.theme = load_theme(theme, &theme_dirs);
     config
 }
 
-fn main() {
-    // DRM device opening
-    use drm::control::Device as ControlDevice;
-
-    // Open DRM device
+fn render_to_png(config: Config, path: String) -> Result<(), Error> {
+    let mut output = HeadlessOutput::new(config.render_size.unwrap_or(DEFAULT_RENDER_SIZE));
+    LoginManager::new(&mut output, config).render()?;
+    output.save_png(path)
+}
 
-    // AUTO-DETECT /dev/dri/card{x}
-    let drm_file = {
-        use std::fs;
-        let dri_dir = "/dev/dri";
-        let mut card_path = None;
-        if let Ok(entries) = fs::read_dir(dri_dir) {
-            for entry in entries.flatten() {
-                let fname = entry.file_name();
-                if fname.to_string_lossy().starts_with("card") {
-                    let fullpath = entry.path();
-                    if let Ok(file) = fs::OpenOptions::new()
-                        .read(true)
-                        .write(true)
-                        .open(&fullpath)
-                    {
-                        card_path = Some(file);
-                        break;
-                    }
-                }
+/// Sets up an output on every connected display, the primary one first.
+fn drm_outputs<'a>(card: &'a Card, choice: &display::OutputChoice) -> Vec<Box<dyn Output + 'a>> {
+    let displays = match display::select_all(card, choice) {
+        Ok(displays) => displays,
+        Err(err) => {
+            eprintln!("{err}");
+            return Vec::new();
+        }
+    };
+    displays
+        .iter()
+        .filter_map(|display| match DrmOutput::new(card, display) {
+            Ok(output) => Some(Box::new(output) as Box<dyn Output + 'a>),
+            Err(err) => {
+                eprintln!("Failed to set up {}: {err}", display.name);
+                None
             }
+        })
+        .collect()
+}
+
+fn main() {
+    let config = parse_args();
+    if let Some(path) = config.render_to.clone() {
+        if let Err(err) = render_to_png(config, path) {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let card = match display::open_card(config.output.card.as_deref()) {
+        Ok(card) => card,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
         }
-        card_path.expect("unable to auto-detect or open any DRM card device in /dev/dri")
     };
 
     // --- DRM master acquisition ---
@@ -205,7 +257,7 @@ fn main() {
     // Official value from <drm/drm.h>: #define DRM_IOCTL_SET_MASTER _IO('d', 0x1e) -> 0x644e
     const DRM_IOCTL_SET_MASTER: libc::c_ulong = 0x644e;
 
-    let fd = drm_file.as_raw_fd();
+    let fd = card.as_raw_fd();
     let _ = unsafe {
         ioctl(
             fd,
@@ -215,50 +267,40 @@ fn main() {
     };
     // --- END drm master acquisition ---
 
-    let card = crate::manager::Card(drm_file);
-
-    // Get available connectors/modes (find connected display)
-    let res_handles = card
-        .resource_handles()
-        .expect("Failed to get DRM resources");
-    let connector_handle = res_handles.connectors()[0]; // TODO: enumerate for active
-    let connector_info = card
-        .get_connector(connector_handle, false)
-        .expect("Failed to get connector info");
-    let crtc_handle = res_handles.crtcs()[0];
-    let mode = connector_info.modes()[0]; // TODO: choose best mode
-
-    let (width, height) = (mode.size().0 as u32, mode.size().1 as u32);
-
-    // Allocate DumbBuffer
-    use drm_fourcc::DrmFourcc;
-
-    let dbuf = card
-        .create_dumb_buffer((width, height), DrmFourcc::Xrgb8888, 32)
-        .expect("Failed to allocate dumb buffer");
-    let fb = card
-        .add_framebuffer(&dbuf, 24, 32)
-        .expect("Failed to add framebuffer");
-
-    // Map DumbBuffer to memory
-    let mut dbuf_mut = dbuf;
-    let map_result = card.map_dumb_buffer(&mut dbuf_mut);
-    let mut map = map_result.expect("Failed to map dumb buffer");
+    let outputs = drm_outputs(&card, &config.output);
+    if outputs.is_empty() {
+        eprintln!("could not drive any display");
+        std::process::exit(1);
+    }
+    let choice = config.output.clone();
+    let mut manager = LoginManager::with_outputs(outputs, config);
+    match NetlinkUevents::open() {
+        Ok(events) => manager.watch_hotplug(Hotplug {
+            events: Box::new(events),
+            reprobe: Box::new(|| drm_outputs(&card, &choice)),
+        }),
+        Err(err) => eprintln!("not watching for display hotplug: {err}"),
+    }
 
     let raw = std::io::stdout()
         .into_raw_mode()
         .expect("unable to enter raw mode");
 
-    // Pass mapped buffer, device, and screen size to LoginManager
-    LoginManager::new(
-        &mut map,
-        parse_args(),
-        width,
-        height,
-        &card,
-        fb.into(),
-        crtc_handle.into(),
-    )
-    .start();
+    manager.start();
     drop(raw);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_are_checked() {
+        assert_eq!(parse_size("1280x720"), Some((1280, 720)));
+        assert_eq!(parse_size("32767x1"), Some((32767, 1)));
+        assert_eq!(parse_size("0x0"), None);
+        assert_eq!(parse_size("640x0"), None);
+        assert_eq!(parse_size("70000x70000"), None);
+        assert_eq!(parse_size("1280"), None);
+    }
+}
@@ -1,17 +1,45 @@
-use libc::{POLLIN, POLLPRI, poll, pollfd};
+use libc::{POLLIN, poll, pollfd};
 use pango::FontDescription;
 use std::fs;
-use std::io::StdinLock;
-use std::os::unix::io::AsRawFd;
+use std::io::Read;
+use std::os::unix::io::RawFd;
 use std::path::Path;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use termion::event::Key;
 use termion::input::TermRead;
 
+use crate::animation::ThemeAnimations;
+use crate::assets::TwoStepAssets;
+use crate::background::{Background, Gradient, Picture};
 use crate::color::Color;
+use crate::display::DialogPlacement;
+use crate::draw::{FramebufferSurface, load_png};
+use crate::hotplug::{self, Hotplug};
+use crate::layout::{self, Widget, WidgetKind};
+use crate::output::Output;
+use crate::pointer::Pointer;
+use crate::script::{ScriptError, ScriptTheme};
+use crate::slideshow::Slideshow;
+use crate::theme::{DEFAULT_TITLE_FONT, Module};
 
+use crate::greetd::{AuthPrompt, AuthReply, AuthRequest, AuthResult, AuthStep, GreetdError};
 use crate::{Config, greetd};
 const USERNAME_CAP: usize = 64;
 const PASSWORD_CAP: usize = 64;
+const ANSWER_CAP: usize = 64;
+
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+const SPINNER_INTERVAL: Duration = Duration::from_millis(100);
+const CANCEL_TIMEOUT: Duration = Duration::from_secs(1);
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+/// How long "Password changed" stays up before the session starts.
+const PASSWORD_CHANGED_HOLD: Duration = Duration::from_millis(1500);
+
+const POWER_MENU: &str = "F1 Power off   F2 Reboot";
+/// How long a power key stays armed waiting for the confirming press.
+const POWER_CONFIRM_TIMEOUT: Duration = Duration::from_secs(5);
 
 const LAST_USER_USERNAME: &str = "/var/cache/ndlm/lastuser";
 const LAST_SESSION_NAME: &str = "/var/cache/ndlm/lastsession";
@@ -22,15 +50,247 @@ const LAST_SESSION_NAME: &str = "/var/cache/ndlm/lastsession";
 enum Mode {
     EditingUsername,
     EditingPassword,
+    /// Answering a follow-up PAM prompt, e.g. a one-time code.
+    Answering,
+    /// Filling in the expired-password screen.
+    ChangingPassword,
+}
+
+#[derive(PartialEq, Copy, Clone)]
+enum ChangeField {
+    Current,
+    New,
+    Confirm,
+}
+
+impl ChangeField {
+    /// Picks the field a PAM password-change prompt is asking for.
+    fn for_prompt(text: &str) -> Self {
+        if has_word(text, &["retype", "again", "confirm"]) {
+            ChangeField::Confirm
+        } else if has_word(text, &["new"]) {
+            ChangeField::New
+        } else {
+            ChangeField::Current
+        }
+    }
+}
+
+/// Whether `text` has one of `words` as a whole word, ignoring case, so
+/// "old" is not found in "hold".
+fn has_word(text: &str, words: &[&str]) -> bool {
+    text.split(|c: char| !c.is_alphanumeric())
+        .any(|word| words.iter().any(|w| word.eq_ignore_ascii_case(w)))
+}
+
+fn is_password_change_prompt(text: &str) -> bool {
+    has_word(text, &["new", "current", "old", "retype"])
+}
+
+/// Whether a PAM message says the password has to be changed, as
+/// pam_unix's "You are required to change your password immediately" or
+/// sssd's "Password expired. Change your password now." do.
+fn is_expiry_notice(text: &str) -> bool {
+    has_word(text, &["expired"]) || text.to_lowercase().contains("change your password")
+}
+
+/// Answers collected on the expired-password screen.
+///
+/// PAM asks for each field in its own secret prompt; once the user submits
+/// the screen, those prompts are answered from here without showing them.
+struct PasswordChange {
+    current: String,
+    new: String,
+    confirm: String,
+    focus: ChangeField,
+    submitted: bool,
+    sent: Vec<ChangeField>,
+}
+
+impl PasswordChange {
+    fn new(current: String) -> Self {
+        Self {
+            focus: if current.is_empty() {
+                ChangeField::Current
+            } else {
+                ChangeField::New
+            },
+            current,
+            new: String::with_capacity(PASSWORD_CAP),
+            confirm: String::with_capacity(PASSWORD_CAP),
+            submitted: false,
+            sent: Vec::new(),
+        }
+    }
+
+    fn field_mut(&mut self, field: ChangeField) -> &mut String {
+        match field {
+            ChangeField::Current => &mut self.current,
+            ChangeField::New => &mut self.new,
+            ChangeField::Confirm => &mut self.confirm,
+        }
+    }
+
+    fn focus_next(&mut self) {
+        self.focus = match self.focus {
+            ChangeField::Current => ChangeField::New,
+            ChangeField::New => ChangeField::Confirm,
+            ChangeField::Confirm => ChangeField::Current,
+        }
+    }
+
+    /// Asks the user for a new password again, and for the current one
+    /// too if that is what was `rejected`. Every field is answered afresh
+    /// on the next submit.
+    fn retry(&mut self, rejected: ChangeField) {
+        if rejected == ChangeField::Current {
+            self.current.clear();
+            self.focus = ChangeField::Current;
+        } else {
+            self.focus = ChangeField::New;
+        }
+        self.new.clear();
+        self.confirm.clear();
+        self.submitted = false;
+        self.sent.clear();
+    }
+
+    /// The answer for a PAM prompt, or `None` if the user has to (re)fill
+    /// the screen first. A prompt repeated after being answered means PAM
+    /// rejected that field.
+    fn answer_for(&mut self, text: &str) -> Option<String> {
+        if !self.submitted {
+            return None;
+        }
+        let field = ChangeField::for_prompt(text);
+        if self.sent.contains(&field) {
+            self.retry(field);
+            return None;
+        }
+        self.sent.push(field);
+        Some(self.field_mut(field).clone())
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+enum MessageKind {
+    Info,
+    Error,
+}
+
+/// Text from greetd or PAM shown in the dialog until it times out.
+struct Message {
+    text: String,
+    kind: MessageKind,
+    shown_at: Instant,
+}
+
+/// Backoff schedule while greetd can't be reached.
+struct Reconnect {
+    at: Instant,
+    delay: Duration,
+    reason: String,
+}
+
+impl Reconnect {
+    fn new(reason: String) -> Self {
+        Self {
+            at: Instant::now() + RECONNECT_MIN,
+            delay: RECONNECT_MIN,
+            reason,
+        }
+    }
+
+    fn backoff(&mut self, reason: String) {
+        self.delay = (self.delay * 2).min(RECONNECT_MAX);
+        self.at = Instant::now() + self.delay;
+        self.reason = reason;
+    }
+}
+
+/// Unbuffered stdin, so polling fd 0 never misses keys already read into a
+/// userspace buffer.
+struct RawStdin;
+
+impl Read for RawStdin {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) };
+        if n < 0 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(n as usize)
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum SessionKind {
+    X11,
+    Wayland,
 }
 
 #[derive(Clone, Debug)]
 pub struct Session {
     pub name: String,
     pub exec: Vec<String>,
+    /// Desktop file ID, i.e. the file name without `.desktop`.
+    pub id: String,
+    /// `None` for the fallback session built from `--session`.
+    pub kind: Option<SessionKind>,
+    pub desktop_names: Vec<String>,
+}
+
+impl Session {
+    fn fallback(exec: Vec<String>) -> Self {
+        Session {
+            name: "Default".to_string(),
+            exec,
+            id: String::new(),
+            kind: None,
+            desktop_names: Vec::new(),
+        }
+    }
+
+    /// Environment passed to greetd's `StartSession`.
+    ///
+    /// The XDG variables derived from the desktop entry come first, then the
+    /// global `--env` values, then the `--session-env` values for this
+    /// session; a later assignment of the same variable wins.
+    pub fn env(&self, config: &Config) -> Vec<String> {
+        let mut env: Vec<String> = Vec::new();
+        if let Some(kind) = self.kind {
+            let kind = match kind {
+                SessionKind::X11 => "x11",
+                SessionKind::Wayland => "wayland",
+            };
+            env.push(format!("XDG_SESSION_TYPE={kind}"));
+        }
+        if !self.id.is_empty() {
+            let current = if self.desktop_names.is_empty() {
+                self.id.clone()
+            } else {
+                self.desktop_names.join(":")
+            };
+            env.push(format!("XDG_CURRENT_DESKTOP={current}"));
+            env.push(format!("XDG_SESSION_DESKTOP={}", self.id));
+        }
+        let extra = config.env.iter().chain(
+            config
+                .session_env
+                .iter()
+                .filter(|(session, _)| *session == self.id || *session == self.name)
+                .map(|(_, var)| var),
+        );
+        for var in extra {
+            let key = var.split('=').next().unwrap_or_default();
+            env.retain(|v| v.split('=').next() != Some(key));
+            env.push(var.clone());
+        }
+        env
+    }
 }
 
-fn parse_desktop_entry(path: &Path) -> Option<Session> {
+fn parse_desktop_entry(path: &Path, kind: SessionKind) -> Option<Session> {
     let entry = match freedesktop_entry_parser::parse_entry(path) {
         Ok(v) => v,
         Err(e) => panic!("{}", e),
@@ -52,7 +312,29 @@ fn parse_desktop_entry(path: &Path) -> Option<Session> {
         Err(e) => panic!("{}", e),
     };
 
-    Some(Session { name, exec })
+    let desktop_names = entry
+        .section("Desktop Entry")
+        .attr("DesktopNames")
+        .map(|v| {
+            v.split(';')
+                .filter(|n| !n.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let id = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    Some(Session {
+        name,
+        exec,
+        id,
+        kind: Some(kind),
+        desktop_names,
+    })
 }
 
 fn load_sessions() -> Vec<Session> {
@@ -62,17 +344,20 @@ fn load_sessions() -> Vec<Session> {
     let mut dirs = Vec::new();
     for base_dir in xdg_data_dirs.split(':') {
         if !base_dir.is_empty() {
-            dirs.push(format!("{}/xsessions", base_dir));
-            dirs.push(format!("{}/wayland-sessions", base_dir));
+            dirs.push((format!("{}/xsessions", base_dir), SessionKind::X11));
+            dirs.push((
+                format!("{}/wayland-sessions", base_dir),
+                SessionKind::Wayland,
+            ));
         }
     }
 
-    for dir in &dirs {
+    for (dir, kind) in &dirs {
         if let Ok(entries) = fs::read_dir(dir) {
             for entry in entries.filter_map(Result::ok) {
                 let path = entry.path();
                 if path.extension().and_then(|s| s.to_str()) == Some("desktop")
-                    && let Some(session) = parse_desktop_entry(&path)
+                    && let Some(session) = parse_desktop_entry(&path, *kind)
                 {
                     sessions.push(session);
                 }
@@ -84,59 +369,86 @@ fn load_sessions() -> Vec<Session> {
     sessions
 }
 
-pub struct Card(pub std::fs::File);
-
-impl std::os::unix::io::AsFd for Card {
-    fn as_fd(&self) -> std::os::unix::io::BorrowedFd<'_> {
-        self.0.as_fd()
-    }
-}
-
-impl std::os::unix::io::AsRawFd for Card {
-    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
-        self.0.as_raw_fd()
-    }
+/// What a script theme was last asked to show.
+#[derive(Debug, Clone, PartialEq)]
+enum ScriptPrompt {
+    Normal,
+    Password { prompt: String, bullets: usize },
+    Question { prompt: String, entry: String },
 }
 
-use drm::Device;
-impl Device for Card {}
-
-use drm::control::Device as ControlDevice;
-impl ControlDevice for Card {}
-
 pub struct LoginManager<'a> {
-    buf: &'a mut [u8],
-    screen_size: (u32, u32),
+    /// Every screen, the primary one first.
+    outputs: Vec<Box<dyn Output + 'a>>,
+    /// The output `draw` is drawing on.
+    drawing: usize,
+    /// The output the dialog is on, unless it is mirrored on all of them.
+    dialog_output: usize,
+    /// Mice, when the dialog follows the pointer.
+    pointer: Option<Pointer>,
+    /// Pointer position across the outputs laid side by side.
+    pointer_x: i32,
+    /// Set up the outputs again when displays are plugged in or removed.
+    hotplug: Option<Hotplug<'a>>,
     mode: Mode,
-    greetd: greetd::GreetD,
+    /// `None` while greetd is unreachable and `reconnect` is scheduled.
+    auth: Option<greetd::AuthWorker>,
+    reconnect: Option<Reconnect>,
+    /// Set while a request to greetd is in flight.
+    busy_since: Option<Instant>,
     config: Config,
-    stdin_keys: termion::input::Keys<StdinLock<'static>>,
+    stdin_keys: termion::input::Keys<RawStdin>,
     username: String,
     password: String,
+    prompt: Option<AuthPrompt>,
+    answer: String,
+    password_sent: bool,
+    /// Set once PAM says the password expired; only then are secret
+    /// prompts taken for the expired-password screen.
+    password_expired: bool,
+    password_change: Option<PasswordChange>,
+    message: Option<Message>,
     should_quit: bool,
-    drm_card: Option<&'a crate::manager::Card>, // DRM device handle
-    fb_id: u32,
-    crtc_id: u32,
     sessions: Vec<Session>,
     current_session: Session,
+    assets: TwoStepAssets,
+    animations: ThemeAnimations,
+    /// The background at each output size.
+    backgrounds: Vec<Background>,
+    background_image: Option<cairo::ImageSurface>,
+    slideshow: Option<Slideshow>,
+    /// The slideshow picture in `backgrounds`.
+    background_slide: Option<usize>,
+    /// The next slideshow picture while it fades in, at each output size.
+    fading_in: Option<(usize, Vec<Background>)>,
+    /// Images of the layout's image widgets, by widget index.
+    widget_images: Vec<Option<cairo::ImageSurface>>,
+    script: Option<ScriptTheme>,
+    /// The prompt last passed to the script and whether it drew it itself.
+    script_prompt: Option<(ScriptPrompt, bool)>,
+    script_message: Option<String>,
+    /// Power key pressed once and waiting for a second press, and when.
+    power_armed: Option<(u8, Instant)>,
+    /// Runs the power action, `systemctl` outside of tests.
+    power_command: &'static str,
+    /// The power action running, to report if it fails.
+    power_child: Option<(&'static str, Child)>,
 }
 
 impl<'a> LoginManager<'a> {
-    pub fn new(
-        buf: &'a mut [u8],
-        config: Config,
-        width: u32,
-        height: u32,
-        drm_card: &'a crate::manager::Card,
-        fb_id: u32,
-        crtc_id: u32,
-    ) -> Self {
+    /// Creates the login screen without talking to greetd yet; `start`
+    /// connects, while `render` only draws a single frame.
+    pub fn new(output: &'a mut dyn Output, config: Config) -> Self {
+        Self::with_outputs(vec![Box::new(output)], config)
+    }
+
+    /// Like `new`, for several screens. The first output is the primary
+    /// one; the dialog goes where `config.output.dialog` says and the
+    /// others show the background.
+    pub fn with_outputs(outputs: Vec<Box<dyn Output + 'a>>, config: Config) -> Self {
         let mut sessions = load_sessions();
         if sessions.is_empty() && !config.session.is_empty() {
-            sessions.push(Session {
-                name: "Default".to_string(),
-                exec: config.session.clone(),
-            });
+            sessions.push(Session::fallback(config.session.clone()));
         }
 
         let selected_session_idx = if !config.session.is_empty() {
@@ -157,131 +469,1138 @@ impl<'a> LoginManager<'a> {
         let current_session = sessions
             .get(selected_session_idx)
             .cloned()
-            .unwrap_or_else(|| Session {
-                name: "Default".to_string(),
-                exec: config.session.clone(),
-            });
+            .unwrap_or_else(|| Session::fallback(config.session.clone()));
+
+        let module = &config.theme.module;
+        let assets = TwoStepAssets::load(&module.image_dir);
+        let animations = ThemeAnimations::load(&module.image_dir, module.animation_frame_rate);
+        let script = load_script(module, outputs[0].size());
+        let background_image = module.background_image.as_ref().and_then(|file| {
+            let image = load_png(Path::new(file));
+            if image.is_none() {
+                eprintln!("could not load background image {file}");
+            }
+            image
+        });
+        let slideshow = module.background_slideshow.as_ref().and_then(|dir| {
+            let slideshow = Slideshow::load(
+                Path::new(dir),
+                module.background_slideshow_interval,
+                module.background_slideshow_fade,
+            );
+            if slideshow.is_none() {
+                eprintln!("no background images in {dir}");
+            }
+            slideshow
+        });
+        let widget_images = config
+            .theme
+            .layout
+            .widgets
+            .iter()
+            .map(|w| match (w.kind, &w.path) {
+                (WidgetKind::Image, Some(path)) => load_png(path),
+                _ => None,
+            })
+            .collect();
 
+        let pointer_x = outputs[0].size().0 as i32 / 2;
         Self {
-            buf,
-            screen_size: (width, height),
+            outputs,
+            drawing: 0,
+            dialog_output: 0,
+            pointer: None,
+            pointer_x,
+            hotplug: None,
+            assets,
+            animations,
+            backgrounds: Vec::new(),
+            background_image,
+            slideshow,
+            background_slide: None,
+            fading_in: None,
+            widget_images,
+            script,
+            script_prompt: None,
+            script_message: None,
+            power_armed: None,
+            power_command: "systemctl",
+            power_child: None,
             mode: Mode::EditingUsername,
-            greetd: greetd::GreetD::new(),
-            stdin_keys: std::io::stdin().lock().keys(),
+            auth: None,
+            reconnect: None,
+            busy_since: None,
+            stdin_keys: RawStdin.keys(),
             username: String::with_capacity(USERNAME_CAP),
             password: String::with_capacity(PASSWORD_CAP),
+            prompt: None,
+            answer: String::with_capacity(ANSWER_CAP),
+            password_sent: false,
+            password_expired: false,
+            password_change: None,
+            message: None,
             config,
             should_quit: false,
-            drm_card: Some(drm_card),
-            fb_id,
-            crtc_id,
             sessions,
             current_session,
         }
     }
 
-    fn wait_for_drm_event(&self) {
-        if let Some(card) = self.drm_card {
-            let fd = card.as_raw_fd();
-            let mut fds = [pollfd {
+    /// Hands the greetd connection to the auth worker, or schedules retries
+    /// if it could not be established.
+    fn connect(&mut self, greetd: AuthResult<greetd::GreetD>) {
+        match greetd {
+            Ok(greetd) => {
+                self.auth = Some(greetd::AuthWorker::spawn(greetd));
+                self.reconnect = None;
+            }
+            Err(err) => self.reconnect = Some(Reconnect::new(err.to_string())),
+        }
+    }
+
+    /// Rebuilds the outputs with `hotplug` whenever a display is plugged
+    /// in or removed.
+    pub fn watch_hotplug(&mut self, hotplug: Hotplug<'a>) {
+        self.hotplug = Some(hotplug);
+    }
+
+    /// Waits until a key is available, the mouse moves, a display is
+    /// plugged in, an animation frame is due or `timeout` elapses; returns
+    /// whether there is a key to read.
+    fn wait_for_input(&self, timeout: Option<Duration>) -> bool {
+        let mice = self.pointer.as_ref().map(Pointer::fds).unwrap_or_default();
+        let uevents = self.hotplug.as_ref().and_then(|h| h.events.fd());
+        let mut fds: Vec<pollfd> = std::iter::once(libc::STDIN_FILENO)
+            .chain(mice)
+            .chain(uevents)
+            .chain(self.frame_fds())
+            .map(|fd| pollfd {
                 fd,
-                events: (POLLIN | POLLPRI),
+                events: POLLIN,
                 revents: 0,
-            }];
-            let res = unsafe { poll(fds.as_mut_ptr(), 1, -1) }; // -1 = infinite timeout
-            if res > 0 && (fds[0].revents & (POLLIN | POLLPRI)) != 0 {
-                let _ = card.receive_events();
-            } else if res < 0 {
-                eprintln!("poll() error while waiting for drm event");
+            })
+            .collect();
+        let timeout = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+        let res = unsafe { poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+        res > 0 && (fds[0].revents & POLLIN) != 0
+    }
+
+    /// Size of the output being drawn.
+    fn output_size(&self) -> (u32, u32) {
+        self.outputs[self.drawing].size()
+    }
+
+    /// Whether the dialog is drawn on output `index`. A script theme lays
+    /// out its sprites for the primary screen only, so it stays there.
+    fn shows_dialog(&self, index: usize) -> bool {
+        if self.script.is_some() {
+            return index == 0;
+        }
+        self.config.output.dialog == DialogPlacement::Mirror || index == self.dialog_output
+    }
+
+    /// Opens the mice when the dialog follows the pointer across several
+    /// screens. Opened afresh after a hotplug, which may bring new ones, as
+    /// a docking station does.
+    fn open_pointer(&mut self) {
+        let follows =
+            self.config.output.dialog == DialogPlacement::Pointer && self.script.is_none();
+        self.pointer = (follows && self.outputs.len() > 1).then(Pointer::open);
+    }
+
+    /// Probes the displays again after a display hotplug event, and opens
+    /// the mice again after either kind, so a new mouse is picked up and
+    /// the dialog is placed again.
+    fn handle_hotplug(&mut self) {
+        let Some(hotplug) = self.hotplug.as_mut() else {
+            return;
+        };
+        let changes = hotplug::drain(hotplug.events.as_mut());
+        if changes.displays {
+            self.reprobe();
+        }
+        if changes.displays || changes.input {
+            self.open_pointer();
+            if self.pointer.is_some() {
+                self.move_pointer(0);
+            }
+        }
+    }
+
+    /// Replaces the outputs with those the displays now give. The script
+    /// theme is run again for the new primary size and backgrounds are
+    /// rendered anew on the next frame.
+    fn reprobe(&mut self) {
+        let Some(hotplug) = self.hotplug.as_mut() else {
+            return;
+        };
+        let outputs = (hotplug.reprobe)();
+        if outputs.is_empty() {
+            eprintln!("no display left to draw on, keeping the previous outputs");
+            return;
+        }
+        let primary = outputs[0].size();
+        let resized = primary != self.outputs[0].size();
+        self.outputs = outputs;
+        self.drawing = 0;
+        self.dialog_output = 0;
+        if resized && self.script.is_some() {
+            self.script = load_script(&self.config.theme.module, primary);
+            self.script_prompt = None;
+            self.script_message = None;
+        }
+        self.backgrounds.clear();
+        self.fading_in = None;
+    }
+
+    /// Moves the pointer by `dx` and the dialog to the output under it.
+    fn move_pointer(&mut self, dx: i32) {
+        let widths: Vec<i32> = self.outputs.iter().map(|o| o.size().0 as i32).collect();
+        let total: i32 = widths.iter().sum();
+        self.pointer_x = self
+            .pointer_x
+            .saturating_add(dx)
+            .clamp(0, (total - 1).max(0));
+        let mut left = 0;
+        for (index, width) in widths.into_iter().enumerate() {
+            if self.pointer_x < left + width {
+                self.dialog_output = index;
+                break;
             }
+            left += width;
         }
     }
 
+    /// Renders the background with `slide` of the slideshow, or else the
+    /// theme's background image.
+    fn render_background(
+        &self,
+        size: (u32, u32),
+        slide: Option<usize>,
+    ) -> Result<Background, crate::Error> {
+        let module = &self.config.theme.module;
+        let (start, end) = self
+            .script
+            .as_ref()
+            .and_then(ScriptTheme::background)
+            .unwrap_or((module.background_start_color, module.background_end_color));
+        let gradient = Gradient {
+            start,
+            end,
+            direction: module.background_gradient_direction,
+            dither: module.background_dither,
+        };
+        let slide_image = slide
+            .zip(self.slideshow.as_ref())
+            .and_then(|(slide, slideshow)| slideshow.image(slide));
+        let picture = slide_image
+            .as_ref()
+            .or(self.background_image.as_ref())
+            .map(|image| Picture {
+                image,
+                mode: module.background_image_mode,
+                tint: module.background_tint,
+                blur: module.background_blur.max(0.0).round() as u32,
+            });
+        let tile = self.assets.background_tile.as_ref();
+        Ok(Background::new(size, &gradient, tile, picture.as_ref())?)
+    }
+
+    /// Renders the background for output sizes that have none yet or when
+    /// the slideshow picture changed, and the picture fading in when it
+    /// starts to.
+    fn update_background(&mut self) -> Result<(), crate::Error> {
+        if let Some(slideshow) = self.slideshow.as_mut() {
+            slideshow.update();
+        }
+        let slide = self.slideshow.as_ref().map(Slideshow::current);
+        if slide != self.background_slide {
+            self.backgrounds = match self.fading_in.take() {
+                Some((faded, backgrounds)) if Some(faded) == slide => backgrounds,
+                _ => Vec::new(),
+            };
+            self.background_slide = slide;
+        }
+        let next = self
+            .slideshow
+            .as_ref()
+            .and_then(Slideshow::fading)
+            .map(|(next, _)| next);
+        if self.fading_in.as_ref().map(|(slide, _)| *slide) != next {
+            self.fading_in = next.map(|next| (next, Vec::new()));
+        }
+
+        let sizes: Vec<_> = self.outputs.iter().map(|o| o.size()).collect();
+        self.backgrounds.retain(|b| sizes.contains(&b.size()));
+        for &size in &sizes {
+            if sized(&self.backgrounds, size).is_none() {
+                let background = self.render_background(size, slide)?;
+                self.backgrounds.push(background);
+            }
+            if let Some((next, _)) = self
+                .fading_in
+                .as_ref()
+                .filter(|(_, backgrounds)| sized(backgrounds, size).is_none())
+            {
+                let background = self.render_background(size, Some(*next))?;
+                if let Some((_, backgrounds)) = self.fading_in.as_mut() {
+                    backgrounds.push(background);
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn clear_surface(&self, surf: &crate::draw::FramebufferSurface) {
-        let bg = &self.config.theme.module.background_start_color;
-        surf.fill_rect(
-            0,
-            0,
-            self.screen_size.0 as i32,
-            self.screen_size.1 as i32,
-            bg,
-        );
+        let size = self.output_size();
+        if let Some(background) = sized(&self.backgrounds, size) {
+            surf.draw_image(background.surface(), 0, 0);
+        }
+        let fade = self.slideshow.as_ref().and_then(Slideshow::fading);
+        if let (Some((_, fading_in)), Some((_, progress))) = (&self.fading_in, fade)
+            && let Some(background) = sized(fading_in, size)
+        {
+            surf.draw_image_with_opacity(background.surface(), 0, 0, progress);
+        }
+    }
+
+    /// Text of a title widget: `--title`, the widget's text, the theme's
+    /// `Title`, or its description or name when the theme sets a `TitleFont`.
+    fn title(&self, text: Option<&str>) -> Option<String> {
+        let theme = &self.config.theme;
+        self.config
+            .title
+            .clone()
+            .or_else(|| text.map(str::to_string))
+            .or_else(|| theme.module.title.clone())
+            .or_else(|| {
+                theme.module.title_font.as_ref()?;
+                theme
+                    .description
+                    .clone()
+                    .or_else(|| Some(theme.name.clone()))
+            })
+            .filter(|t| !t.is_empty())
+    }
+
+    /// The widget's font, or the theme's font for its kind.
+    fn widget_font(&self, widget: &Widget) -> FontDescription {
+        let module = &self.config.theme.module;
+        match (&widget.font, widget.kind) {
+            (Some(font), _) => font.clone(),
+            (None, WidgetKind::Title) => module
+                .title_font
+                .clone()
+                .unwrap_or_else(|| FontDescription::from_string(DEFAULT_TITLE_FONT)),
+            (None, WidgetKind::Session | WidgetKind::Message | WidgetKind::Power) => {
+                module.small_font.clone()
+            }
+            (None, _) => module.font.clone(),
+        }
+    }
+
+    /// What a text widget shows and in which color, or `None` if it has
+    /// nothing to show right now.
+    fn widget_text(&self, widget: &Widget) -> Option<(String, Color)> {
+        let module = &self.config.theme.module;
+        let color = |default: Color| widget.color.unwrap_or(default);
+        let field_color = |focused: bool| match focused {
+            true => widget.focused_color.unwrap_or(module.focused_text_color),
+            false => color(module.text_color),
+        };
+        let label = |default: &str| widget.text.as_deref().unwrap_or(default).to_string();
+        match widget.kind {
+            WidgetKind::Title => {
+                let title = self.title(widget.text.as_deref())?;
+                Some((title, color(module.title_color)))
+            }
+            WidgetKind::Clock => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                let text = layout::format_time(widget.clock_format(), now.as_secs() as i64);
+                Some((text, color(module.text_color)))
+            }
+            WidgetKind::Username => Some((
+                format!("{}{}", label("Username: "), self.username),
+                field_color(self.mode == Mode::EditingUsername),
+            )),
+            WidgetKind::Password => {
+                let text = match (&self.mode, &self.prompt) {
+                    (Mode::Answering, Some(prompt)) => {
+                        let shown = match prompt {
+                            AuthPrompt::Secret(_) => "*".repeat(self.answer.chars().count()),
+                            _ => self.answer.clone(),
+                        };
+                        format!("{} {shown}", prompt.text().trim_end())
+                    }
+                    _ => {
+                        let stars = "*".repeat(self.password.chars().count());
+                        format!("{}{stars}", label("Password: "))
+                    }
+                };
+                Some((text, field_color(self.mode != Mode::EditingUsername)))
+            }
+            WidgetKind::Session => {
+                let default = match self.sessions.len() {
+                    0 => return None,
+                    1 => "Session: ",
+                    _ => "Session (←/→): ",
+                };
+                let text = format!("{}{}", label(default), self.current_session.name);
+                Some((text, color(module.session_color)))
+            }
+            WidgetKind::Message => self.status_line(),
+            WidgetKind::Power => Some((label(POWER_MENU), color(module.text_color))),
+            WidgetKind::Background | WidgetKind::Image => None,
+        }
+    }
+
+    /// Draws one widget of the layout at its place.
+    fn draw_widget(&self, surf: &FramebufferSurface, index: usize) {
+        let widget = &self.config.theme.layout.widgets[index];
+        let screen = self.output_size();
+        let padding = widget.padding;
+        let fill = |(x, y): (i32, i32), (w, h): (i32, i32)| {
+            if let Some(background) = &widget.background {
+                surf.fill_rounded_rect(x, y, w, h, widget.radius, background);
+            }
+        };
+        match widget.kind {
+            WidgetKind::Background => {
+                let size = (
+                    widget.width.unwrap_or(screen.0 as i32),
+                    widget.height.unwrap_or(screen.1 as i32),
+                );
+                let color = widget.background.or(widget.color).unwrap_or_default();
+                let (x, y) = widget.position(size, screen);
+                surf.fill_rounded_rect(x, y, size.0, size.1, widget.radius, &color);
+            }
+            WidgetKind::Image => {
+                let Some(image) = &self.widget_images[index] else {
+                    return;
+                };
+                let size = widget.size((image.width(), image.height()));
+                let (x, y) = widget.position(size, screen);
+                fill((x, y), size);
+                let (w, h) = (size.0 - 2 * padding, size.1 - 2 * padding);
+                if (w, h) == (image.width(), image.height()) {
+                    surf.draw_image(image, x + padding, y + padding);
+                } else {
+                    surf.draw_image_scaled(image, x + padding, y + padding, w, h);
+                }
+            }
+            _ => {
+                let Some((text, color)) = self.widget_text(widget) else {
+                    return;
+                };
+                let font = self.widget_font(widget);
+                let (text_w, text_h) = surf.text_size(&text, &font);
+                let size = widget.size((text_w, text_h));
+                let (x, y) = widget.position(size, screen);
+                fill((x, y), size);
+                let dx = widget.text_offset(size.0, text_w);
+                let dy = (size.1 - 2 * padding - text_h).max(0) / 2;
+                surf.draw_text(&text, &font, &color, x + dx, y + padding + dy);
+            }
+        }
     }
 
-    fn draw_prompt_surface(&self, surf: &mut crate::draw::FramebufferSurface, offset: (u32, u32)) {
-        let stars = "*".repeat(self.password.len());
-        let font = FontDescription::from_string("DejaVu Sans Mono 18");
-        let font_small = FontDescription::from_string("DejaVu Sans Mono 14");
-        let (username_color, password_color) = match self.mode {
-            Mode::EditingUsername => (Color::YELLOW, Color::WHITE),
-            Mode::EditingPassword => (Color::WHITE, Color::YELLOW),
+    /// Lines shown in place of the prompt fields on the retry and
+    /// expired-password screens, with whether each uses the small font.
+    fn form_lines(&self) -> Option<Vec<(String, Color, bool)>> {
+        let module = &self.config.theme.module;
+        if let Some(reconnect) = &self.reconnect {
+            return Some(vec![
+                (
+                    "greetd unavailable, retrying…".to_string(),
+                    module.message_error_color,
+                    false,
+                ),
+                (String::new(), module.text_color, false),
+                (reconnect.reason.clone(), module.text_color, true),
+            ]);
+        }
+        let (Mode::ChangingPassword, Some(change)) = (self.mode, &self.password_change) else {
+            return None;
+        };
+        let fields = [
+            ("Current password", ChangeField::Current, &change.current),
+            ("New password", ChangeField::New, &change.new),
+            ("Confirm password", ChangeField::Confirm, &change.confirm),
+        ];
+        let mut lines: Vec<_> = fields
+            .into_iter()
+            .map(|(label, field, value)| {
+                let color = if change.focus == field {
+                    module.focused_text_color
+                } else {
+                    module.text_color
+                };
+                let text = format!("{label}: {}", "*".repeat(value.chars().count()));
+                (text, color, false)
+            })
+            .collect();
+        lines.extend(self.status_line().map(|(text, color)| (text, color, true)));
+        Some(lines)
+    }
+
+    /// Draws form lines from the username field down, a row apart; a row is
+    /// the distance from the username to the password field.
+    fn draw_form(
+        &self,
+        surf: &FramebufferSurface,
+        username: &Widget,
+        lines: &[(String, Color, bool)],
+    ) {
+        let screen = self.output_size();
+        let font = self.widget_font(username);
+        let small_font = &self.config.theme.module.small_font;
+        let (_, line_h) = surf.text_size("Ag", &font);
+        let top = |widget: &Widget| widget.position(widget.size((0, line_h)), screen).1;
+        let row = match self.config.theme.layout.find(WidgetKind::Password) {
+            Some(password) if top(password) > top(username) => top(password) - top(username),
+            _ => username.size((0, line_h)).1,
         };
-        let (x, y) = (offset.0 - 120, offset.1 - 40);
-
-        let bg = &self.config.theme.module.background_start_color;
-        surf.fill_input_region(x as i32, y as i32, 480, 90, bg);
-        surf.draw_text_region(
-            &format!("Username: {}", self.username),
-            &font,
-            &username_color,
-            0,
+        let (x, y) = username.position(username.size((0, line_h)), screen);
+        let (x, y) = (x + username.padding, y + username.padding);
+        for (index, (text, color, small)) in lines.iter().enumerate() {
+            let font = if *small { small_font } else { &font };
+            surf.draw_text(text, font, color, x, y + index as i32 * row);
+        }
+    }
+
+    /// Draws the widgets below the animations, or the ones above them. The
+    /// built-in prompt is left out while a two-step or script theme draws its
+    /// own, and replaced by the form on the retry and password-change screens.
+    fn draw_widgets(&self, surf: &FramebufferSurface, below: bool) {
+        let layout = &self.config.theme.layout;
+        let themed_prompt = self.script_draws_prompt() || self.uses_two_step_prompt();
+        let form = self.form_lines();
+        for index in layout.z_order() {
+            let widget = &layout.widgets[index];
+            if (widget.z() < 0) != below || (themed_prompt && widget.kind.is_prompt()) {
+                continue;
+            }
+            match &form {
+                Some(lines) if widget.kind.is_prompt() => {
+                    if widget.kind == WidgetKind::Username {
+                        self.draw_form(surf, widget, lines);
+                    }
+                }
+                _ => self.draw_widget(surf, index),
+            }
+        }
+    }
+
+    /// Draws the current frame of each animation centered on the theme's
+    /// `HorizontalAlignment` and `VerticalAlignment`, where plymouth's
+    /// two-step plugin left it when boot finished.
+    fn draw_animations(&self, surf: &FramebufferSurface) {
+        let module = &self.config.theme.module;
+        let (screen_w, screen_h) = self.output_size();
+        if let Some(animation) = self.animations.current() {
+            let frame = animation.frame();
+            let x = (screen_w as f32 * module.horizontal_alignment) as i32 - frame.width() / 2;
+            let y = (screen_h as f32 * module.vertical_alignment) as i32 - frame.height() / 2;
+            surf.draw_image(frame, x, y);
+        }
+    }
+
+    /// Whether the prompt is drawn from the theme's two-step images.
+    fn uses_two_step_prompt(&self) -> bool {
+        self.assets.entry.is_some()
+            && self.reconnect.is_none()
+            && self.mode != Mode::ChangingPassword
+    }
+
+    /// Draws the prompt the way plymouth's two-step plugin does: lock icon
+    /// and entry centered in the dialog box, bullets for secret input, the
+    /// prompt label above the box and session and messages below it.
+    fn draw_two_step_prompt(&self, surf: &FramebufferSurface) {
+        let Some(entry) = &self.assets.entry else {
+            return;
+        };
+        let module = &self.config.theme.module;
+        let (font, font_small) = (&module.font, &module.small_font);
+        let (screen_w, screen_h) = self.output_size();
+        let size = |image: &Option<cairo::ImageSurface>| {
+            image.as_ref().map_or((0, 0), |i| (i.width(), i.height()))
+        };
+        let (entry_w, entry_h) = (entry.width(), entry.height());
+        let (lock_w, lock_h) = size(&self.assets.lock);
+        let (box_w, box_h) = match &self.assets.dialog_box {
+            Some(b) => (b.width(), b.height()),
+            None => (lock_w + entry_w, lock_h.max(entry_h)),
+        };
+        let box_x = ((screen_w as i32 - box_w) as f32 * module.dialog_horizontal_alignment) as i32;
+        let box_y = ((screen_h as i32 - box_h) as f32 * module.dialog_vertical_alignment) as i32;
+        if let Some(dialog_box) = &self.assets.dialog_box {
+            surf.draw_image(dialog_box, box_x, box_y);
+        }
+        let lock_x = box_x + (box_w - (lock_w + entry_w)) / 2;
+        let entry_x = lock_x + lock_w;
+        let entry_y = box_y + (box_h - entry_h) / 2;
+        if let Some(lock) = &self.assets.lock {
+            surf.draw_image(lock, lock_x, box_y + (box_h - lock_h) / 2);
+        }
+        surf.draw_image(entry, entry_x, entry_y);
+
+        let (label, value, secret) = match (&self.mode, &self.prompt) {
+            (Mode::Answering, Some(prompt)) => (
+                prompt.text().trim_end().to_string(),
+                &self.answer,
+                matches!(prompt, AuthPrompt::Secret(_)),
+            ),
+            (Mode::EditingPassword, _) => (
+                format!("Password for {}", self.username),
+                &self.password,
+                true,
+            ),
+            _ => ("Username".to_string(), &self.username, false),
+        };
+        let padding = entry_h / 4;
+        match (&self.assets.bullet, secret) {
+            (Some(bullet), true) => {
+                let room = ((entry_w - 2 * padding) / bullet.width().max(1)).max(0) as usize;
+                let y = entry_y + (entry_h - bullet.height()) / 2;
+                for i in 0..value.chars().count().min(room) {
+                    surf.draw_image(bullet, entry_x + padding + i as i32 * bullet.width(), y);
+                }
+            }
+            _ => {
+                let text = if secret {
+                    "*".repeat(value.chars().count())
+                } else {
+                    value.clone()
+                };
+                let (_, text_h) = surf.text_size(&text, font);
+                let y = entry_y + (entry_h - text_h) / 2;
+                surf.draw_text(&text, font, &module.input_text_color, entry_x + padding, y);
+            }
+        }
+
+        let center_x = box_x + box_w / 2;
+        let (label_w, label_h) = surf.text_size(&label, font);
+        surf.draw_text(
+            &label,
+            font,
+            &module.text_color,
+            center_x - label_w / 2,
+            box_y - label_h - 8,
         );
-        surf.draw_text_region(&format!("Password: {stars}"), &font, &password_color, 24);
 
-        // Draw horizontal session list
-        if !self.sessions.is_empty() {
-            let session_y_offset = 56 + 10; // 10px below password field
+        let mut y = box_y + box_h + 8;
+        for (text, color) in self.footer_lines(true) {
+            let (w, h) = surf.text_size(&text, font_small);
+            surf.draw_text(&text, font_small, &color, center_x - w / 2, y);
+            y += h + 4;
+        }
+    }
 
-            if self.sessions.len() == 1 {
-                let text = format!("Session: {}", self.current_session.name);
-                surf.draw_text_region(&text, &font_small, &Color::YELLOW, session_y_offset);
-            } else {
-                let text = format!("Session (←/→): {}", self.current_session.name);
-                surf.draw_text_region(&text, &font_small, &Color::YELLOW, session_y_offset);
+    /// Status and session lines shown under a themed prompt.
+    fn footer_lines(&self, with_status: bool) -> Vec<(String, Color)> {
+        let mut lines: Vec<_> = self
+            .status_line()
+            .filter(|_| with_status)
+            .into_iter()
+            .collect();
+        match self.sessions.len() {
+            0 => {}
+            1 => lines.push((
+                format!("Session: {}", self.current_session.name),
+                self.config.theme.module.session_color,
+            )),
+            _ => lines.push((
+                format!("Session (←/→): {}", self.current_session.name),
+                self.config.theme.module.session_color,
+            )),
+        }
+        lines
+    }
+
+    /// The prompt a script theme should show for the current mode.
+    fn script_prompt(&self) -> ScriptPrompt {
+        if self.reconnect.is_some() {
+            return ScriptPrompt::Normal;
+        }
+        match (self.mode, &self.prompt) {
+            (Mode::EditingUsername, _) => ScriptPrompt::Question {
+                prompt: "Username".into(),
+                entry: self.username.clone(),
+            },
+            (Mode::EditingPassword, _) => ScriptPrompt::Password {
+                prompt: format!("Password for {}", self.username),
+                bullets: self.password.chars().count(),
+            },
+            (Mode::Answering, Some(AuthPrompt::Secret(text))) => ScriptPrompt::Password {
+                prompt: text.trim_end().to_string(),
+                bullets: self.answer.chars().count(),
+            },
+            (Mode::Answering, Some(AuthPrompt::Visible(text))) => ScriptPrompt::Question {
+                prompt: text.trim_end().to_string(),
+                entry: self.answer.clone(),
+            },
+            _ => ScriptPrompt::Normal,
+        }
+    }
+
+    /// Runs the script theme's callbacks for this frame. A theme failing at
+    /// run time is dropped in favour of the built-in dialog.
+    fn update_script(&mut self) {
+        let Some(mut script) = self.script.take() else {
+            return;
+        };
+        match self.feed_script(&mut script) {
+            Ok(()) => self.script = Some(script),
+            Err(err) => {
+                eprintln!("script theme failed, falling back to the built-in dialog: {err}");
+                self.script_prompt = None;
             }
         }
+    }
+
+    /// Calls the refresh callback, and the prompt and message callbacks
+    /// when what they show has changed, as plymouth does.
+    fn feed_script(&mut self, script: &mut ScriptTheme) -> Result<(), ScriptError> {
+        if script.has_refresh() {
+            script.refresh()?;
+        }
+        let prompt = self.script_prompt();
+        if self.script_prompt.as_ref().map(|(p, _)| p) != Some(&prompt) {
+            let handled = match &prompt {
+                ScriptPrompt::Password { prompt, bullets } => {
+                    script.display_password(prompt, *bullets)?
+                }
+                ScriptPrompt::Question { prompt, entry } => {
+                    script.display_question(prompt, entry)?
+                }
+                ScriptPrompt::Normal => {
+                    script.display_normal()?;
+                    false
+                }
+            };
+            self.script_prompt = Some((prompt, handled));
+        }
+        let message = self.message.as_ref().map(|m| m.text.clone());
+        if message != self.script_message {
+            script.display_message(message.as_deref().unwrap_or_default())?;
+            self.script_message = message;
+        }
+        Ok(())
+    }
+
+    /// Whether the script theme drew the current prompt itself.
+    fn script_draws_prompt(&self) -> bool {
+        self.script.is_some()
+            && self
+                .script_prompt
+                .as_ref()
+                .is_some_and(|(_, handled)| *handled)
+    }
+
+    /// Draws the session and status at the bottom of the screen, for
+    /// prompts a script theme drew itself.
+    fn draw_script_footer(&self, surf: &FramebufferSurface) {
+        let font_small = &self.config.theme.module.small_font;
+        let (screen_w, screen_h) = self.output_size();
+        let themed_messages = self.script.as_ref().is_some_and(ScriptTheme::has_message);
+        let lines = self.footer_lines(self.busy_since.is_some() || !themed_messages);
+        let mut y = screen_h as i32 - 16;
+        for (text, color) in lines.iter().rev() {
+            let (w, h) = surf.text_size(text, font_small);
+            y -= h;
+            surf.draw_text(text, font_small, color, (screen_w as i32 - w) / 2, y);
+            y -= 4;
+        }
+    }
 
-        surf.composite_region_to_fb();
+    /// The busy spinner or the current message, with its color.
+    fn status_line(&self) -> Option<(String, Color)> {
+        let module = &self.config.theme.module;
+        if let Some(since) = self.busy_since {
+            let frame = (since.elapsed().as_millis() / SPINNER_INTERVAL.as_millis()) as usize;
+            let text = format!("Authenticating… {}", SPINNER[frame % SPINNER.len()]);
+            return Some((text, module.message_info_color));
+        }
+        let message = self.message.as_ref()?;
+        let color = match message.kind {
+            MessageKind::Info => module.message_info_color,
+            MessageKind::Error => module.message_error_color,
+        };
+        Some((message.text.clone(), color))
     }
 
     fn goto_next_mode(&mut self) {
         self.mode = match self.mode {
             Mode::EditingUsername => Mode::EditingPassword,
             Mode::EditingPassword => Mode::EditingUsername,
+            Mode::Answering => Mode::Answering,
+            Mode::ChangingPassword => {
+                if let Some(change) = self.password_change.as_mut() {
+                    change.focus_next();
+                }
+                Mode::ChangingPassword
+            }
         }
     }
 
-    fn draw(&mut self) {
-        let xoff = self.config.theme.module.dialog_horizontal_alignment;
-        let yoff = self.config.theme.module.dialog_vertical_alignment;
-        let x = (self.screen_size.0 as f32 * xoff) as u32;
-        let y = (self.screen_size.1 as f32 * yoff) as u32;
-        let mut mut_surface = crate::draw::FramebufferSurface::new(self.buf, self.screen_size)
-            .expect("could not create framebuffer surface");
-        self.clear_surface(&mut_surface);
-        self.draw_prompt_surface(&mut mut_surface, (x, y));
-        if let Some(card) = self.drm_card {
-            use drm::control::Device as _;
-            card.page_flip(
-                drm::control::crtc::Handle::from(
-                    std::num::NonZeroU32::new(self.crtc_id).expect("CRTC id must be nonzero"),
-                ),
-                drm::control::framebuffer::Handle::from(
-                    std::num::NonZeroU32::new(self.fb_id).expect("FB id must be nonzero"),
-                ),
-                drm::control::PageFlipFlags::EVENT,
-                None,
-            )
-            .expect("DRM page flip failed");
+    fn reset_login(&mut self) {
+        self.username = String::with_capacity(USERNAME_CAP);
+        self.password = String::with_capacity(PASSWORD_CAP);
+        self.answer = String::with_capacity(ANSWER_CAP);
+        self.prompt = None;
+        self.password_sent = false;
+        self.password_expired = false;
+        self.password_change = None;
+        self.mode = Mode::EditingUsername;
+        if let Some(auth) = &self.auth {
+            auth.send(AuthRequest::Cancel);
+        }
+    }
+
+    /// Hands a request to the auth worker; input is ignored until it answers.
+    fn request(&mut self, request: AuthRequest) {
+        if let Some(auth) = &self.auth {
+            self.busy_since = Some(Instant::now());
+            auth.send(request);
+        }
+    }
+
+    fn poll_auth(&mut self) {
+        while let Some(reply) = self.auth.as_ref().and_then(|auth| auth.try_recv()) {
+            match reply {
+                AuthReply::Step(step) => {
+                    self.busy_since = None;
+                    self.advance(step);
+                }
+                AuthReply::SessionStarted(result) => {
+                    self.busy_since = None;
+                    self.session_started(result);
+                }
+                AuthReply::Cancelled(Err(err)) if err.is_connection_lost() => {
+                    self.disconnect(err);
+                }
+                AuthReply::Cancelled(_) => {}
+            }
+        }
+    }
+
+    /// Drops a broken greetd connection and shows the retry screen.
+    fn disconnect(&mut self, err: GreetdError) {
+        self.auth = None;
+        self.busy_since = None;
+        self.reset_login();
+        self.reconnect = Some(Reconnect::new(err.to_string()));
+    }
+
+    fn try_reconnect(&mut self) {
+        let Some(reconnect) = self.reconnect.as_mut() else {
+            return;
+        };
+        if Instant::now() < reconnect.at {
+            return;
+        }
+        match greetd::GreetD::new() {
+            Ok(greetd) => {
+                self.auth = Some(greetd::AuthWorker::spawn(greetd));
+                self.reconnect = None;
+            }
+            Err(err) => reconnect.backoff(err.to_string()),
+        }
+    }
+
+    /// The page flips in flight while an animation plays. Each one that
+    /// completes wakes the main loop to draw the next frame, so playback
+    /// is paced by the display like plymouth's, without a timer.
+    fn frame_fds(&self) -> Vec<RawFd> {
+        if self.animations.next_frame_in().is_none() {
+            return Vec::new();
+        }
+        let mut fds: Vec<RawFd> = self.outputs.iter().filter_map(|o| o.flip_fd()).collect();
+        // Outputs on one card share its descriptor.
+        fds.sort_unstable();
+        fds.dedup();
+        fds
+    }
+
+    /// How long the main loop may sleep before something needs redrawing.
+    ///
+    /// Animation frames follow page flips; only outputs that show frames at
+    /// once, with no flip to wait for, fall back to a frame timer. Since
+    /// frames are picked by elapsed time, either way a late wakeup skips
+    /// frames rather than slowing playback.
+    fn next_wakeup(&self) -> Option<Duration> {
+        let spinner = self.busy_since.map(|_| SPINNER_INTERVAL);
+        let reconnect = self
+            .reconnect
+            .as_ref()
+            .map(|r| r.at.saturating_duration_since(Instant::now()));
+        let rate = self.config.theme.module.animation_frame_rate.max(1.0);
+        let refresh = self
+            .script
+            .as_ref()
+            .filter(|s| s.has_refresh())
+            .map(|_| Duration::from_secs_f32(1.0 / rate));
+        [
+            spinner,
+            reconnect,
+            self.message_timeout(),
+            self.animations
+                .next_frame_in()
+                .filter(|_| self.frame_fds().is_empty()),
+            refresh,
+            self.clock_tick(),
+            self.power_child.as_ref().map(|_| SPINNER_INTERVAL),
+            self.slideshow
+                .as_ref()
+                .and_then(|s| s.next_change_in(Duration::from_secs_f32(1.0 / rate))),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    /// Time until the text of a clock widget next changes.
+    fn clock_tick(&self) -> Option<Duration> {
+        let clocks = self.config.theme.layout.widgets.iter();
+        let seconds = clocks
+            .filter(|w| w.kind == WidgetKind::Clock)
+            .map(|w| layout::shows_seconds(w.clock_format()))
+            .max()?;
+        let period = Duration::from_secs(if seconds { 1 } else { 60 });
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let into_period = now.as_nanos() % period.as_nanos();
+        Some(period - Duration::from_nanos(into_period as u64))
+    }
+
+    /// Handles power menu key `key`, F1 or F2. The first press asks for
+    /// confirmation; pressing the same key again within
+    /// `POWER_CONFIRM_TIMEOUT` starts the action without waiting for it.
+    fn power(&mut self, key: u8) {
+        let (action, name) = match key {
+            1 => ("poweroff", "power off"),
+            _ => ("reboot", "reboot"),
+        };
+        let confirmed = self
+            .power_armed
+            .take()
+            .is_some_and(|(armed, at)| armed == key && at.elapsed() < POWER_CONFIRM_TIMEOUT);
+        if !confirmed {
+            self.power_armed = Some((key, Instant::now()));
+            self.show_message(format!("Press F{key} again to {name}"), MessageKind::Info);
+            return;
+        }
+        self.message = None;
+        match Command::new(self.power_command).arg(action).spawn() {
+            Ok(child) => self.power_child = Some((action, child)),
+            Err(err) => self.show_message(format!("{action} failed: {err}"), MessageKind::Error),
+        }
+    }
+
+    /// Reports a power action that exited with an error.
+    fn check_power_action(&mut self) {
+        let Some((action, child)) = self.power_child.as_mut() else {
+            return;
+        };
+        let text = match child.try_wait() {
+            Ok(None) => return,
+            Ok(Some(status)) if status.success() => None,
+            Ok(Some(status)) => Some(format!("{action} failed: {status}")),
+            Err(err) => Some(format!("{action} failed: {err}")),
+        };
+        self.power_child = None;
+        if let Some(text) = text {
+            self.show_message(text, MessageKind::Error);
+        }
+    }
+
+    fn session_started(&mut self, result: AuthResult<()>) {
+        match result {
+            Ok(_) => {
+                if self.password_change.is_some() {
+                    self.show_message("Password changed", MessageKind::Info);
+                    // The main loop stops drawing once `should_quit` is set.
+                    if let Err(err) = self.draw() {
+                        eprintln!("could not draw frame: {err}");
+                    }
+                }
+                let _ = fs::write(LAST_USER_USERNAME, self.username.clone());
+                let _ = fs::write(LAST_SESSION_NAME, self.current_session.name.clone());
+                self.should_quit = true;
+            }
+            Err(err) => self.fail(err),
         }
     }
 
+    fn show_message(&mut self, text: impl Into<String>, kind: MessageKind) {
+        self.message = Some(Message {
+            text: text.into(),
+            kind,
+            shown_at: Instant::now(),
+        });
+    }
+
+    /// Time left before the current message should disappear, if any.
+    fn message_timeout(&self) -> Option<Duration> {
+        let timeout = self.config.theme.module.message_timeout;
+        let message = self.message.as_ref()?;
+        if timeout <= 0.0 {
+            return None;
+        }
+        let timeout = Duration::try_from_secs_f32(timeout).unwrap_or(Duration::MAX);
+        Some(timeout.saturating_sub(message.shown_at.elapsed()))
+    }
+
+    /// Takes the next step of the PAM conversation.
+    ///
+    /// The first secret prompt is answered with the password typed on the
+    /// login screen. Once PAM has said the password expired, password-change
+    /// prompts go to the expired-password screen; every other question is
+    /// shown to the user, and info or error messages are acknowledged after
+    /// being put in the message area.
+    fn advance(&mut self, step: AuthResult<AuthStep>) {
+        let request = match step {
+            Ok(AuthStep::Authenticated) => AuthRequest::StartSession(
+                self.current_session.exec.clone(),
+                self.current_session.env(&self.config),
+            ),
+            Ok(AuthStep::Prompt(AuthPrompt::Secret(_))) if !self.password_sent => {
+                self.password_sent = true;
+                AuthRequest::Respond(Some(self.password.clone()))
+            }
+            Ok(AuthStep::Prompt(AuthPrompt::Secret(text)))
+                if self.password_change.is_some()
+                    || (self.password_expired && is_password_change_prompt(&text)) =>
+            {
+                let password = self.password.clone();
+                let change = self
+                    .password_change
+                    .get_or_insert_with(|| PasswordChange::new(password));
+                match change.answer_for(&text) {
+                    Some(answer) => AuthRequest::Respond(Some(answer)),
+                    None => {
+                        self.prompt = Some(AuthPrompt::Secret(text));
+                        self.mode = Mode::ChangingPassword;
+                        return;
+                    }
+                }
+            }
+            Ok(AuthStep::Prompt(prompt)) if !prompt.expects_answer() => {
+                self.password_expired |= is_expiry_notice(prompt.text());
+                let kind = match prompt {
+                    AuthPrompt::Error(_) => MessageKind::Error,
+                    _ => MessageKind::Info,
+                };
+                self.show_message(prompt.text(), kind);
+                AuthRequest::Respond(None)
+            }
+            Ok(AuthStep::Prompt(prompt)) => {
+                self.prompt = Some(prompt);
+                self.answer = String::with_capacity(ANSWER_CAP);
+                self.mode = Mode::Answering;
+                return;
+            }
+            Err(err) => {
+                self.fail(err);
+                return;
+            }
+        };
+        self.request(request);
+    }
+
+    fn fail(&mut self, err: GreetdError) {
+        if err.is_connection_lost() {
+            self.disconnect(err);
+            return;
+        }
+        let text = if self.password_change.is_some() {
+            format!("Password change failed: {err}")
+        } else {
+            err.to_string()
+        };
+        self.show_message(text, MessageKind::Error);
+        self.reset_login();
+    }
+
+    /// Validates the expired-password screen and resumes the conversation.
+    fn submit_password_change(&mut self) {
+        let Some(change) = self.password_change.as_mut() else {
+            return;
+        };
+        if change.new.is_empty() {
+            change.focus = ChangeField::New;
+            return;
+        }
+        if change.new != change.confirm {
+            change.retry(ChangeField::New);
+            self.show_message("Passwords do not match", MessageKind::Error);
+            return;
+        }
+        change.submitted = true;
+        self.message = None;
+        if let Some(prompt) = self.prompt.take() {
+            self.advance(Ok(AuthStep::Prompt(prompt)));
+        }
+    }
+
+    fn draw(&mut self) -> Result<(), crate::Error> {
+        self.update_script();
+        self.update_background()?;
+        for index in 0..self.outputs.len() {
+            self.drawing = index;
+            let screen_size = self.output_size();
+            let pitch = self.outputs[index].pitch();
+            let mut_surface = crate::draw::FramebufferSurface::new(
+                self.outputs[index].buffer(),
+                screen_size,
+                pitch,
+            )?;
+            self.clear_surface(&mut_surface);
+            if !self.shows_dialog(index) {
+                continue;
+            }
+            self.draw_widgets(&mut_surface, true);
+            self.draw_animations(&mut_surface);
+            if let Some(script) = self.script.as_mut() {
+                script.draw(&mut_surface);
+            }
+            if self.script_draws_prompt() {
+                self.draw_script_footer(&mut_surface);
+            } else if self.uses_two_step_prompt() {
+                self.draw_two_step_prompt(&mut_surface);
+            }
+            self.draw_widgets(&mut_surface, false);
+        }
+        self.drawing = 0;
+        self.outputs
+            .iter_mut()
+            .try_for_each(|output| output.present())
+    }
+
     fn handle_keyboard(&mut self) {
         let key = self
             .stdin_keys
             .next()
             .and_then(Result::ok)
             .unwrap_or_else(|| quit());
+        self.handle_key(key);
+    }
+
+    fn handle_key(&mut self, key: Key) {
+        if let Key::F(n @ (1 | 2)) = key
+            && self.config.theme.layout.has(WidgetKind::Power)
+        {
+            self.power(n);
+            return;
+        }
+        self.power_armed = None;
+        let blocked = self.busy_since.is_some() || self.reconnect.is_some();
+        if blocked && !matches!(key, Key::Ctrl('c') | Key::Ctrl('d')) {
+            return;
+        }
 
         match key {
             Key::Left => {
@@ -315,7 +1634,14 @@ impl<'a> LoginManager<'a> {
             Key::Ctrl('c') | Key::Ctrl('d') => {
                 self.username.clear();
                 self.password.clear();
-                self.greetd.cancel();
+                if let Some(auth) = &self.auth {
+                    auth.send(AuthRequest::Cancel);
+                    if self.busy_since.is_none() {
+                        // Let an idle worker deliver the cancel before we exit;
+                        // a busy one is abandoned and greetd cleans up on close.
+                        auth.recv_timeout(CANCEL_TIMEOUT);
+                    }
+                }
                 self.should_quit = true;
             }
             Key::Backspace => match self.mode {
@@ -325,6 +1651,14 @@ impl<'a> LoginManager<'a> {
                 Mode::EditingPassword => {
                     self.password.pop();
                 }
+                Mode::Answering => {
+                    self.answer.pop();
+                }
+                Mode::ChangingPassword => {
+                    if let Some(change) = self.password_change.as_mut() {
+                        change.field_mut(change.focus).pop();
+                    }
+                }
             },
             Key::Char('\t') => self.goto_next_mode(),
             Key::Char('\n') => match self.mode {
@@ -338,42 +1672,38 @@ impl<'a> LoginManager<'a> {
                         self.username.clear();
                         self.mode = Mode::EditingUsername;
                     } else {
-                        let res = self.greetd.login(
-                            self.username.clone(),
-                            self.password.clone(),
-                            self.current_session.exec.clone(),
-                        );
-                        match res {
-                            Ok(_) => {
-                                let _ = fs::write(LAST_USER_USERNAME, self.username.clone());
-                                let _ =
-                                    fs::write(LAST_SESSION_NAME, self.current_session.name.clone());
-                                self.should_quit = true;
-                            }
-                            Err(_) => {
-                                self.username = String::with_capacity(USERNAME_CAP);
-                                self.password = String::with_capacity(PASSWORD_CAP);
-                                self.mode = Mode::EditingUsername;
-                                self.greetd.cancel();
-                            }
-                        }
+                        self.password_sent = false;
+                        self.message = None;
+                        self.request(AuthRequest::CreateSession(self.username.clone()));
                     }
                 }
+                Mode::Answering => {
+                    self.prompt = None;
+                    let answer =
+                        std::mem::replace(&mut self.answer, String::with_capacity(ANSWER_CAP));
+                    self.request(AuthRequest::Respond(Some(answer)));
+                }
+                Mode::ChangingPassword => match self.password_change.as_mut() {
+                    Some(change) if change.focus != ChangeField::Confirm => change.focus_next(),
+                    _ => self.submit_password_change(),
+                },
             },
             Key::Char(v) => match self.mode {
                 Mode::EditingUsername => self.username.push(v),
                 Mode::EditingPassword => self.password.push(v),
+                Mode::Answering => self.answer.push(v),
+                Mode::ChangingPassword => {
+                    if let Some(change) = self.password_change.as_mut() {
+                        change.field_mut(change.focus).push(v);
+                    }
+                }
             },
             _ => {} // Ignore other keys
         }
     }
 
     fn setup(&mut self) {
-        let mut_surface = crate::draw::FramebufferSurface::new(self.buf, self.screen_size)
-            .expect("could not create framebuffer surface");
-        self.clear_surface(&mut_surface);
-        self.draw();
-        self.wait_for_drm_event(); // Wait for initial flip event
+        self.draw().expect("could not draw initial frame");
         if let Ok(user) = fs::read_to_string(LAST_USER_USERNAME) {
             self.username = user;
             self.mode = Mode::EditingPassword;
@@ -386,19 +1716,500 @@ impl<'a> LoginManager<'a> {
         };
     }
 
+    /// Draws the login screen once, as it looks before any input.
+    pub fn render(&mut self) -> Result<(), crate::Error> {
+        self.draw()
+    }
+
     pub fn start(&mut self) {
+        self.connect(greetd::GreetD::new());
+        self.open_pointer();
         self.setup();
         loop {
-            self.draw();
-            self.wait_for_drm_event(); // Wait before next draw/flip
-            self.handle_keyboard();
+            self.draw().expect("could not draw frame"); // Waits for the last flip
+            if self.wait_for_input(self.next_wakeup()) {
+                self.handle_keyboard();
+            }
+            if let Some(dx) = self.pointer.as_mut().map(Pointer::motion) {
+                self.move_pointer(dx);
+            }
+            self.handle_hotplug();
+            self.check_power_action();
+            self.poll_auth();
+            self.try_reconnect();
+            if self.message_timeout().is_some_and(|t| t.is_zero()) {
+                self.message = None;
+            }
             if self.should_quit {
+                if self.password_change.is_some() {
+                    std::thread::sleep(PASSWORD_CHANGED_HOLD);
+                }
                 break;
             }
         }
     }
 }
 
+/// Runs the theme's script, if it has one, for a `window_size` screen.
+fn load_script(module: &Module, window_size: (u32, u32)) -> Option<ScriptTheme> {
+    let file = module.script_file.as_ref()?;
+    let image_dir = Path::new(&module.image_dir);
+    ScriptTheme::load(Path::new(file), image_dir, window_size)
+        .inspect_err(|err| eprintln!("could not load script theme {file}: {err}"))
+        .ok()
+}
+
+/// The background of `size` among `backgrounds`.
+fn sized(backgrounds: &[Background], size: (u32, u32)) -> Option<&Background> {
+    backgrounds.iter().find(|b| b.size() == size)
+}
+
 fn quit() -> ! {
     std::process::exit(1);
 }
+
+#[cfg(test)]
+mod snapshots;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::{Animation, Playback};
+    use crate::display::OutputChoice;
+    use crate::hotplug::UeventSource;
+    use crate::mock_greetd::{MockGreetd, script};
+    use crate::output::HeadlessOutput;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    const CMD: &[&str] = &["sway"];
+
+    fn login_manager<'a>(output: &'a mut HeadlessOutput, server: &MockGreetd) -> LoginManager<'a> {
+        let config = Config {
+            session: vec!["sway".into()],
+            ..Config::default()
+        };
+        let mut manager = LoginManager::new(output, config);
+        manager.connect(greetd::GreetD::connect(server.path()));
+        manager.current_session = Session::fallback(vec!["sway".into()]);
+        manager
+    }
+
+    fn type_line(manager: &mut LoginManager<'_>, text: &str) {
+        for c in text.chars() {
+            manager.handle_key(Key::Char(c));
+        }
+        manager.handle_key(Key::Char('\n'));
+    }
+
+    /// Pumps auth replies until the conversation needs the user again.
+    fn settle(manager: &mut LoginManager<'_>) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        manager.poll_auth();
+        while manager.busy_since.is_some() {
+            assert!(Instant::now() < deadline, "greetd conversation stalled");
+            std::thread::sleep(Duration::from_millis(5));
+            manager.poll_auth();
+        }
+    }
+
+    fn message<'m>(manager: &'m LoginManager<'_>) -> Option<(&'m str, MessageKind)> {
+        manager.message.as_ref().map(|m| (m.text.as_str(), m.kind))
+    }
+
+    fn placing_dialog(dialog: DialogPlacement) -> Config {
+        Config {
+            output: OutputChoice {
+                dialog,
+                ..OutputChoice::default()
+            },
+            ..Config::default()
+        }
+    }
+
+    /// Draws a frame on two outputs and returns what each shows.
+    fn two_screens(dialog: DialogPlacement) -> (Vec<u8>, Vec<u8>) {
+        let mut primary = HeadlessOutput::new((320, 240));
+        let mut secondary = HeadlessOutput::new((320, 240));
+        let outputs: Vec<Box<dyn Output + '_>> =
+            vec![Box::new(&mut primary), Box::new(&mut secondary)];
+        LoginManager::with_outputs(outputs, placing_dialog(dialog))
+            .render()
+            .unwrap();
+        (primary.buffer().to_vec(), secondary.buffer().to_vec())
+    }
+
+    #[test]
+    fn the_dialog_is_on_the_primary_output_or_mirrored() {
+        let (primary, secondary) = two_screens(DialogPlacement::Primary);
+        assert_ne!(primary, secondary);
+        let (mirrored, also_mirrored) = two_screens(DialogPlacement::Mirror);
+        assert!(mirrored == primary && also_mirrored == primary);
+    }
+
+    #[test]
+    fn padded_rows_are_drawn_at_the_pitch() {
+        let (width, height) = (330, 200);
+        let mut packed = HeadlessOutput::new((width, height));
+        let mut padded = HeadlessOutput::with_pitch((width, height), 1344);
+        for output in [&mut packed, &mut padded] {
+            LoginManager::new(output, Config::default())
+                .render()
+                .unwrap();
+        }
+        let row = width as usize * 4;
+        let packed_rows = packed.buffer().chunks(row);
+        let padded_rows = padded.buffer().chunks(1344).map(|r| &r[..row]);
+        assert!(packed_rows.eq(padded_rows));
+    }
+
+    #[test]
+    fn the_dialog_follows_the_pointer_across_outputs() {
+        let mut left = HeadlessOutput::new((320, 240));
+        let mut right = HeadlessOutput::new((640, 480));
+        let outputs: Vec<Box<dyn Output + '_>> = vec![Box::new(&mut left), Box::new(&mut right)];
+        let config = placing_dialog(DialogPlacement::Pointer);
+        let mut manager = LoginManager::with_outputs(outputs, config);
+        manager.render().unwrap();
+        assert_eq!(manager.backgrounds.len(), 2);
+        assert_eq!(manager.dialog_output, 0);
+        manager.move_pointer(200);
+        assert_eq!(manager.dialog_output, 1);
+        manager.move_pointer(10_000);
+        assert_eq!((manager.dialog_output, manager.pointer_x), (1, 959));
+        manager.move_pointer(-700);
+        assert_eq!(manager.dialog_output, 0);
+    }
+
+    /// Uevents queued by the test instead of read from the kernel.
+    struct SyntheticUevents(Rc<RefCell<Vec<&'static [u8]>>>);
+
+    impl UeventSource for SyntheticUevents {
+        fn fd(&self) -> Option<RawFd> {
+            None
+        }
+
+        fn next_event(&mut self) -> Option<Vec<u8>> {
+            self.0.borrow_mut().pop().map(<[u8]>::to_vec)
+        }
+    }
+
+    #[test]
+    fn outputs_are_rebuilt_when_a_display_is_plugged_in() {
+        let config = placing_dialog(DialogPlacement::Pointer);
+        let mut laptop = HeadlessOutput::new((320, 240));
+        let mut dock = HeadlessOutput::new((320, 240));
+        let outputs: Vec<Box<dyn Output + '_>> = vec![Box::new(&mut laptop), Box::new(&mut dock)];
+        let mut manager = LoginManager::with_outputs(outputs, config);
+        let queue = Rc::new(RefCell::new(Vec::new()));
+        let probes = Rc::new(RefCell::new(0));
+        let reprobe = {
+            let probes = probes.clone();
+            move || {
+                *probes.borrow_mut() += 1;
+                let sizes = [(400, 300), (640, 480)];
+                sizes
+                    .map(|size| Box::new(HeadlessOutput::new(size)) as Box<dyn Output>)
+                    .into()
+            }
+        };
+        manager.watch_hotplug(Hotplug {
+            events: Box::new(SyntheticUevents(queue.clone())),
+            reprobe: Box::new(reprobe),
+        });
+        manager.render().unwrap();
+        manager.move_pointer(320);
+        assert_eq!(manager.dialog_output, 1);
+
+        queue
+            .borrow_mut()
+            .push(b"add@/devices/usb1/1-1\0ACTION=add\0SUBSYSTEM=usb\0");
+        manager.handle_hotplug();
+        assert_eq!((*probes.borrow(), manager.outputs.len()), (0, 2));
+
+        // A new mouse opens the mice again without probing the displays.
+        manager.pointer = None;
+        queue.borrow_mut().push(
+            b"add@/devices/usb1/1-1/input/input9/event9\0ACTION=add\0\
+              SUBSYSTEM=input\0DEVNAME=input/event9\0",
+        );
+        manager.handle_hotplug();
+        assert_eq!(*probes.borrow(), 0);
+        assert!(manager.pointer.is_some());
+        assert_eq!(manager.dialog_output, 1);
+
+        queue.borrow_mut().extend([
+            b"change@/devices/drm/card0\0SUBSYSTEM=drm\0HOTPLUG=1\0".as_slice(),
+            b"change@/devices/drm/card0\0SUBSYSTEM=drm\0HOTPLUG=1\0".as_slice(),
+        ]);
+        manager.handle_hotplug();
+        assert_eq!((*probes.borrow(), manager.outputs.len()), (1, 2));
+        assert_eq!(manager.outputs[0].size(), (400, 300));
+        // The mice are opened again and the dialog stays under the pointer,
+        // now on the second screen's left half.
+        assert!(manager.pointer.is_some());
+        assert_eq!((manager.pointer_x, manager.dialog_output), (480, 1));
+        manager.render().unwrap();
+        let mut sizes: Vec<_> = manager.backgrounds.iter().map(Background::size).collect();
+        sizes.sort();
+        assert_eq!(sizes, [(400, 300), (640, 480)]);
+    }
+
+    #[test]
+    fn script_themes_stay_on_the_primary_screen() {
+        let dir = std::env::temp_dir().join(format!("ndlm-script-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("width.script");
+        std::fs::write(
+            &script,
+            "Window.SetBackgroundTopColor(Window.GetWidth() / 1000, 0, 0);\n",
+        )
+        .unwrap();
+        let script_width = |manager: &LoginManager<'_>| {
+            let (top, _) = manager.script.as_ref().unwrap().background().unwrap();
+            (top.red * 1000.0).round() as u32
+        };
+
+        for placement in [DialogPlacement::Pointer, DialogPlacement::Mirror] {
+            let mut config = placing_dialog(placement);
+            config.theme.module.script_file = Some(script.display().to_string());
+            let mut laptop = HeadlessOutput::new((320, 240));
+            let mut dock = HeadlessOutput::new((640, 480));
+            let outputs: Vec<Box<dyn Output + '_>> =
+                vec![Box::new(&mut laptop), Box::new(&mut dock)];
+            let mut manager = LoginManager::with_outputs(outputs, config);
+            let queue = Rc::new(RefCell::new(Vec::new()));
+            manager.watch_hotplug(Hotplug {
+                events: Box::new(SyntheticUevents(queue.clone())),
+                reprobe: Box::new(|| {
+                    [(400, 300), (640, 480)]
+                        .map(|size| Box::new(HeadlessOutput::new(size)) as Box<dyn Output>)
+                        .into()
+                }),
+            });
+            manager.render().unwrap();
+            assert_eq!(script_width(&manager), 320);
+            assert!(manager.pointer.is_none());
+            assert!(manager.shows_dialog(0) && !manager.shows_dialog(1));
+
+            queue
+                .borrow_mut()
+                .push(b"change@/devices/drm/card0\0SUBSYSTEM=drm\0HOTPLUG=1\0");
+            manager.handle_hotplug();
+            // The script is run again for the new primary size, and the
+            // mice stay closed.
+            assert_eq!(script_width(&manager), 400);
+            assert!(manager.pointer.is_none());
+            assert!(manager.shows_dialog(0) && !manager.shows_dialog(1));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn one_time_code_is_asked_after_the_password() {
+        let server = MockGreetd::spawn(script::multi_prompt(CMD));
+        let mut output = HeadlessOutput::new((640, 480));
+        let mut manager = login_manager(&mut output, &server);
+        type_line(&mut manager, "alice");
+        type_line(&mut manager, "hunter2");
+        settle(&mut manager);
+        assert!(manager.mode == Mode::Answering);
+        assert_eq!(
+            manager.prompt,
+            Some(AuthPrompt::Visible("Verification code: ".into()))
+        );
+        type_line(&mut manager, "123456");
+        settle(&mut manager);
+        assert!(manager.should_quit);
+        drop(manager);
+        server.finish().unwrap();
+    }
+
+    #[test]
+    fn token_prompts_are_not_taken_for_a_password_change() {
+        let server = MockGreetd::spawn(script::token_code(CMD));
+        let mut output = HeadlessOutput::new((640, 480));
+        let mut manager = login_manager(&mut output, &server);
+        type_line(&mut manager, "alice");
+        type_line(&mut manager, "hunter2");
+        settle(&mut manager);
+        assert!(manager.mode == Mode::Answering);
+        assert!(manager.password_change.is_none());
+        type_line(&mut manager, "123456");
+        settle(&mut manager);
+        assert!(manager.should_quit);
+        drop(manager);
+        server.finish().unwrap();
+    }
+
+    #[test]
+    fn a_rejected_current_password_can_be_entered_again() {
+        let server = MockGreetd::spawn(script::current_password_rejected(CMD));
+        let mut output = HeadlessOutput::new((640, 480));
+        let mut manager = login_manager(&mut output, &server);
+        type_line(&mut manager, "alice");
+        type_line(&mut manager, "hunter2");
+        settle(&mut manager);
+        assert!(manager.mode == Mode::ChangingPassword);
+        type_line(&mut manager, "s3cret");
+        type_line(&mut manager, "s3cret");
+        settle(&mut manager);
+        // The current password is asked again, so it is cleared and focused.
+        assert!(manager.mode == Mode::ChangingPassword);
+        let change = manager.password_change.as_ref().unwrap();
+        assert!(change.focus == ChangeField::Current && change.current.is_empty());
+        type_line(&mut manager, "hunter3");
+        type_line(&mut manager, "s3cret");
+        type_line(&mut manager, "s3cret");
+        settle(&mut manager);
+        assert!(manager.should_quit);
+        assert!(message(&manager) == Some(("Password changed", MessageKind::Info)));
+        // The message was drawn before quitting.
+        let shown = manager.outputs[0].buffer().to_vec();
+        manager.render().unwrap();
+        assert_eq!(manager.outputs[0].buffer(), shown.as_slice());
+        manager.message = None;
+        manager.render().unwrap();
+        assert_ne!(manager.outputs[0].buffer(), shown.as_slice());
+        drop(manager);
+        server.finish().unwrap();
+    }
+
+    #[test]
+    fn power_keys_need_a_second_press() {
+        let mut output = HeadlessOutput::new((320, 240));
+        let mut config = Config::default();
+        let power = toml::from_str("type = \"power\"").unwrap();
+        config.theme.layout.widgets.push(power);
+        let mut manager = LoginManager::new(&mut output, config);
+        manager.power_command = "true";
+        manager.handle_key(Key::F(1));
+        assert!(message(&manager) == Some(("Press F1 again to power off", MessageKind::Info)));
+        assert!(manager.power_child.is_none());
+        // Any other key cancels and is handled as usual.
+        manager.handle_key(Key::Char('a'));
+        assert_eq!(manager.username, "a");
+        manager.handle_key(Key::F(1));
+        manager.handle_key(Key::F(2));
+        assert!(manager.power_child.is_none());
+        manager.handle_key(Key::F(2));
+        let (action, child) = manager.power_child.as_mut().unwrap();
+        assert_eq!(*action, "reboot");
+        child.wait().unwrap();
+        manager.check_power_action();
+        assert!(manager.power_child.is_none() && manager.message.is_none());
+    }
+
+    #[test]
+    fn wrong_password_is_reported_and_form_reset() {
+        let server = MockGreetd::spawn(script::wrong_password());
+        let mut output = HeadlessOutput::new((640, 480));
+        let mut manager = login_manager(&mut output, &server);
+        type_line(&mut manager, "alice");
+        type_line(&mut manager, "hunter2");
+        settle(&mut manager);
+        assert!(!manager.should_quit);
+        assert!(manager.mode == Mode::EditingUsername);
+        assert!(manager.password.is_empty());
+        assert!(message(&manager) == Some(("Authentication failed", MessageKind::Error)));
+        drop(manager);
+        server.finish().unwrap();
+    }
+
+    #[test]
+    fn info_message_is_shown_and_acknowledged() {
+        let server = MockGreetd::spawn(script::info_only(CMD));
+        let mut output = HeadlessOutput::new((640, 480));
+        let mut manager = login_manager(&mut output, &server);
+        type_line(&mut manager, "alice");
+        type_line(&mut manager, "unused");
+        settle(&mut manager);
+        assert!(manager.should_quit);
+        assert!(message(&manager) == Some(("Welcome back", MessageKind::Info)));
+        drop(manager);
+        server.finish().unwrap();
+    }
+
+    #[test]
+    fn failed_session_start_keeps_the_greeter_up() {
+        let server = MockGreetd::spawn(script::start_session_failure(CMD));
+        let mut output = HeadlessOutput::new((640, 480));
+        let mut manager = login_manager(&mut output, &server);
+        type_line(&mut manager, "alice");
+        type_line(&mut manager, "hunter2");
+        settle(&mut manager);
+        assert!(!manager.should_quit);
+        assert!(
+            message(&manager)
+                == Some((
+                    "Could not start session: command not found",
+                    MessageKind::Error
+                ))
+        );
+        drop(manager);
+        server.finish().unwrap();
+    }
+
+    /// An output that says a page flip is in flight on `fd`.
+    struct Flipping(HeadlessOutput, RawFd);
+
+    impl Output for Flipping {
+        fn size(&self) -> (u32, u32) {
+            self.0.size()
+        }
+
+        fn pitch(&self) -> u32 {
+            self.0.pitch()
+        }
+
+        fn buffer(&mut self) -> &mut [u8] {
+            self.0.buffer()
+        }
+
+        fn present(&mut self) -> Result<(), crate::Error> {
+            self.0.present()
+        }
+
+        fn flip_fd(&self) -> Option<RawFd> {
+            Some(self.1)
+        }
+    }
+
+    #[test]
+    fn animation_frames_follow_page_flips() {
+        let throbber = || {
+            let frames = (0..2)
+                .map(|_| cairo::ImageSurface::create(cairo::Format::ARgb32, 1, 1).unwrap())
+                .collect();
+            Animation::new(frames, 30.0, Playback::Loop)
+        };
+        // Without a flip to wait for, frames come on a timer.
+        let mut output = HeadlessOutput::new((320, 240));
+        let mut manager = LoginManager::new(&mut output, Config::default());
+        manager.animations.throbber = throbber();
+        assert!(manager.frame_fds().is_empty());
+        assert!(manager.next_wakeup().unwrap() <= Duration::from_millis(34));
+        drop(manager);
+
+        let outputs: Vec<Box<dyn Output>> = vec![
+            Box::new(Flipping(HeadlessOutput::new((320, 240)), 7)),
+            Box::new(Flipping(HeadlessOutput::new((320, 240)), 7)),
+        ];
+        let mut manager = LoginManager::with_outputs(outputs, Config::default());
+        assert!(manager.frame_fds().is_empty());
+        manager.animations.throbber = throbber();
+        assert_eq!(manager.frame_fds(), [7]);
+        assert!(manager.next_wakeup().is_none());
+    }
+
+    #[test]
+    fn unreachable_greetd_shows_retry_screen() {
+        let mut output = HeadlessOutput::new((640, 480));
+        let mut manager = LoginManager::new(&mut output, Config::default());
+        manager.connect(Err(GreetdError::SocketMissing));
+        assert!(manager.reconnect.is_some());
+        type_line(&mut manager, "alice");
+        assert!(manager.username.is_empty());
+        assert!(manager.next_wakeup().is_some());
+    }
+}
@@ -0,0 +1,367 @@
+//! Golden-image tests for the login dialog.
+//!
+//! Each test renders one state through `HeadlessOutput` and compares it with
+//! `tests/snapshots/<name>.png`. Run with `UPDATE_SNAPSHOTS=1` to rewrite
+//! the references after an intended visual change.
+
+use std::path::PathBuf;
+
+use cairo::ImageSurface;
+
+use super::*;
+use crate::output::HeadlessOutput;
+
+const SIZE: (u32, u32) = (800, 450);
+/// Largest per-channel difference still treated as the same pixel, to absorb
+/// antialiasing differences between font rasterizer versions.
+const CHANNEL_TOLERANCE: u8 = 24;
+/// Share of pixels allowed to differ beyond `CHANNEL_TOLERANCE`.
+const PIXEL_TOLERANCE: f64 = 0.002;
+
+const THEME: &str = "\
+[Plymouth Theme]
+Name=Snapshot
+ModuleName=two-step
+
+[two-step]
+DialogHorizontalAlignment=.5
+DialogVerticalAlignment=.5
+BackgroundStartColor=0x1e1e2e
+BackgroundEndColor=0x11111b
+";
+
+fn session(name: &str) -> Session {
+    Session {
+        name: name.to_string(),
+        ..Session::fallback(vec![name.to_lowercase()])
+    }
+}
+
+fn login_manager(output: &mut HeadlessOutput) -> LoginManager<'_> {
+    let config = Config {
+        theme: THEME.parse().unwrap(),
+        ..Config::default()
+    };
+    let mut manager = LoginManager::new(output, config);
+    manager.sessions = Vec::new();
+    manager.current_session = Session::fallback(Vec::new());
+    manager
+}
+
+fn snapshot_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(format!("{name}.png"))
+}
+
+/// Compares the rendered frame with its reference image.
+fn assert_snapshot(name: &str, output: &mut HeadlessOutput) {
+    let path = snapshot_path(name);
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        output.save_png(&path).unwrap();
+        return;
+    }
+    let actual_path = std::env::temp_dir().join(format!("ndlm-{name}.actual.png"));
+    let mut file = std::fs::File::open(&path)
+        .unwrap_or_else(|e| panic!("missing snapshot {}: {e}", path.display()));
+    let mut expected = ImageSurface::create_from_png(&mut file).unwrap();
+    if (expected.width() as u32, expected.height() as u32) != output.size() {
+        output.save_png(&actual_path).unwrap();
+        panic!("{name}: size differs, see {}", actual_path.display());
+    }
+    let stride = expected.stride() as usize;
+    let (width, height) = output.size();
+    let (width, pitch) = (width as usize, output.pitch() as usize);
+    let expected = expected.data().unwrap();
+    let actual = output.buffer();
+    let mut differing = 0usize;
+    for (row, expected_row) in expected.chunks(stride).enumerate() {
+        let actual_row = &actual[row * pitch..row * pitch + width * 4];
+        for (e, a) in expected_row[..width * 4]
+            .chunks(4)
+            .zip(actual_row.chunks(4))
+        {
+            // Bytes are B, G, R, X; the padding byte carries nothing.
+            if e[..3]
+                .iter()
+                .zip(&a[..3])
+                .any(|(e, a)| e.abs_diff(*a) > CHANNEL_TOLERANCE)
+            {
+                differing += 1;
+            }
+        }
+    }
+    let share = differing as f64 / (width * height as usize) as f64;
+    if share > PIXEL_TOLERANCE {
+        output.save_png(&actual_path).unwrap();
+        panic!(
+            "{name}: {differing} pixels differ from {}, see {}",
+            path.display(),
+            actual_path.display()
+        );
+    }
+}
+
+#[test]
+fn username_focused() {
+    // Padded rows, as some drivers give, must look the same.
+    let padded = HeadlessOutput::with_pitch(SIZE, SIZE.0 * 4 + 64);
+    for mut output in [HeadlessOutput::new(SIZE), padded] {
+        let mut manager = login_manager(&mut output);
+        manager.username = "alice".into();
+        manager.render().unwrap();
+        drop(manager);
+        assert_snapshot("username_focused", &mut output);
+    }
+}
+
+#[test]
+fn password_focused() {
+    let mut output = HeadlessOutput::new(SIZE);
+    let mut manager = login_manager(&mut output);
+    manager.username = "alice".into();
+    manager.password = "hunter2".into();
+    manager.mode = Mode::EditingPassword;
+    manager.render().unwrap();
+    drop(manager);
+    assert_snapshot("password_focused", &mut output);
+}
+
+#[test]
+fn single_session() {
+    let mut output = HeadlessOutput::new(SIZE);
+    let mut manager = login_manager(&mut output);
+    manager.sessions = vec![session("Sway")];
+    manager.current_session = session("Sway");
+    manager.render().unwrap();
+    drop(manager);
+    assert_snapshot("single_session", &mut output);
+}
+
+#[test]
+fn many_sessions() {
+    let mut output = HeadlessOutput::new(SIZE);
+    let mut manager = login_manager(&mut output);
+    manager.sessions = vec![session("GNOME"), session("Hyprland"), session("Sway")];
+    manager.current_session = session("Hyprland");
+    manager.render().unwrap();
+    drop(manager);
+    assert_snapshot("many_sessions", &mut output);
+}
+
+#[test]
+fn error_message() {
+    let mut output = HeadlessOutput::new(SIZE);
+    let mut manager = login_manager(&mut output);
+    manager.show_message("Authentication failed", MessageKind::Error);
+    manager.render().unwrap();
+    drop(manager);
+    assert_snapshot("error_message", &mut output);
+}
+
+/// Writes a minimal two-step image set, drawn with cairo so the test does
+/// not depend on any installed plymouth theme.
+fn write_two_step_assets(dir: &std::path::Path) {
+    std::fs::create_dir_all(dir).unwrap();
+    let images = [
+        ("box.png", 360, 90, [0.2, 0.2, 0.3, 0.8]),
+        ("entry.png", 240, 36, [0.9, 0.9, 0.9, 1.0]),
+        ("lock.png", 28, 36, [0.8, 0.7, 0.2, 1.0]),
+        ("bullet.png", 14, 14, [0.1, 0.1, 0.1, 1.0]),
+    ];
+    for (name, width, height, [r, g, b, a]) in images {
+        let surface = ImageSurface::create(cairo::Format::ARgb32, width, height).unwrap();
+        let context = cairo::Context::new(&surface).unwrap();
+        context.set_source_rgba(r, g, b, a);
+        context.paint().unwrap();
+        drop(context);
+        let mut file = std::fs::File::create(dir.join(name)).unwrap();
+        surface.write_to_png(&mut file).unwrap();
+    }
+}
+
+#[test]
+fn two_step_password_prompt() {
+    let dir = std::env::temp_dir().join(format!("ndlm-two-step-{}", std::process::id()));
+    write_two_step_assets(&dir);
+    let theme = format!("{THEME}ImageDir={}\n", dir.display());
+    let mut output = HeadlessOutput::new(SIZE);
+    let config = Config {
+        theme: theme.parse().unwrap(),
+        ..Config::default()
+    };
+    let mut manager = LoginManager::new(&mut output, config);
+    manager.sessions = vec![session("Sway")];
+    manager.current_session = session("Sway");
+    manager.username = "alice".into();
+    manager.password = "hunter2".into();
+    manager.mode = Mode::EditingPassword;
+    manager.render().unwrap();
+    drop(manager);
+    let _ = std::fs::remove_dir_all(&dir);
+    assert_snapshot("two_step_password_prompt", &mut output);
+}
+
+#[test]
+fn title_and_watermark() {
+    let dir = std::env::temp_dir().join(format!("ndlm-watermark-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let watermark = ImageSurface::create(cairo::Format::ARgb32, 120, 40).unwrap();
+    let context = cairo::Context::new(&watermark).unwrap();
+    context.set_source_rgba(0.5, 0.6, 0.9, 1.0);
+    context.paint().unwrap();
+    drop(context);
+    let mut file = std::fs::File::create(dir.join("watermark.png")).unwrap();
+    watermark.write_to_png(&mut file).unwrap();
+
+    let theme = format!(
+        "{THEME}ImageDir={}\nTitleFont=DejaVu Sans Bold 20\nTitleHorizontalAlignment=.5\n\
+         TitleVerticalAlignment=.1\nWatermarkHorizontalAlignment=.95\nWatermarkVerticalAlignment=.95\n",
+        dir.display()
+    );
+    let mut output = HeadlessOutput::new(SIZE);
+    let config = Config {
+        theme: theme.parse().unwrap(),
+        ..Config::default()
+    };
+    let mut manager = LoginManager::new(&mut output, config);
+    manager.sessions = Vec::new();
+    manager.render().unwrap();
+    drop(manager);
+    let _ = std::fs::remove_dir_all(&dir);
+    assert_snapshot("title_and_watermark", &mut output);
+}
+
+const SCRIPT: &str = r#"
+Window.SetBackgroundTopColor(0.15, 0.1, 0.25);
+Window.SetBackgroundBottomColor(0.02, 0.02, 0.05);
+
+logo = Sprite(Image("box.png"));
+logo.SetPosition(Window.GetWidth() / 2 - logo.GetImage().GetWidth() / 2, 60, 1);
+
+fun display_password(prompt, bullets) {
+    global.label = Sprite(Image.Text(prompt, 1, 1, 1, 1, "DejaVu Sans 16"));
+    label.SetPosition(Window.GetWidth() / 2 - label.GetImage().GetWidth() / 2, 200, 2);
+    global.bullets = NULL;
+    bullet = Image("bullet.png");
+    left = Window.GetWidth() / 2 - bullets * 10;
+    for (i = 0; i < bullets; i++) {
+        global.bullets[i] = Sprite(bullet);
+        global.bullets[i].SetPosition(left + i * 20, 250, 2);
+    }
+}
+Plymouth.SetDisplayPasswordFunction(display_password);
+"#;
+
+#[test]
+fn script_password_prompt() {
+    let dir = std::env::temp_dir().join(format!("ndlm-script-{}", std::process::id()));
+    write_two_step_assets(&dir);
+    std::fs::write(dir.join("snapshot.script"), SCRIPT).unwrap();
+    // Without entry.png the two-step prompt stays out of the way.
+    std::fs::remove_file(dir.join("entry.png")).unwrap();
+    let theme = format!(
+        "{}ImageDir={}\nScriptFile=@IMAGEDIR@/snapshot.script\n",
+        THEME.replace("two-step", "script"),
+        dir.display()
+    );
+    let mut output = HeadlessOutput::new(SIZE);
+    let config = Config {
+        theme: theme.parse().unwrap(),
+        ..Config::default()
+    };
+    let mut manager = LoginManager::new(&mut output, config);
+    manager.sessions = vec![session("Sway")];
+    manager.current_session = session("Sway");
+    manager.username = "alice".into();
+    manager.password = "hunter2".into();
+    manager.mode = Mode::EditingPassword;
+    manager.render().unwrap();
+    assert!(manager.script_draws_prompt());
+    drop(manager);
+    let _ = std::fs::remove_dir_all(&dir);
+    assert_snapshot("script_password_prompt", &mut output);
+}
+
+const NATIVE_THEME: &str = r##"
+name = "Native"
+
+[style]
+font = "DejaVu Sans 16"
+background_start_color = "0x2e3440"
+background_end_color = "0x3b4252"
+focused_text_color = "0x88c0d0"
+
+[[widget]]
+type = "background"
+anchor = "center"
+width = 420
+height = 150
+background = "0x4c566acc"
+radius = 16
+
+[[widget]]
+type = "title"
+anchor = "center"
+y = -110
+text = "Welcome"
+
+[[widget]]
+type = "username"
+anchor = "center"
+y = -40
+width = 380
+height = 32
+padding = 4
+background = "0x3b4252"
+radius = 6
+z = 1
+
+[[widget]]
+type = "password"
+anchor = "center"
+y = 0
+width = 380
+height = 32
+padding = 4
+background = "0x3b4252"
+radius = 6
+z = 1
+
+[[widget]]
+type = "session"
+anchor = "center"
+y = 45
+align = "center"
+width = 380
+z = 1
+
+[[widget]]
+type = "power"
+anchor = "bottom-right"
+x = -16
+y = -12
+"##;
+
+#[test]
+fn native_theme_widgets() {
+    let dir = std::env::temp_dir().join(format!("ndlm-native-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("native.toml");
+    std::fs::write(&path, NATIVE_THEME).unwrap();
+    let mut output = HeadlessOutput::new(SIZE);
+    let config = Config {
+        theme: crate::theme::Theme::from_file(&path).unwrap(),
+        ..Config::default()
+    };
+    let mut manager = LoginManager::new(&mut output, config);
+    manager.sessions = vec![session("GNOME"), session("Sway")];
+    manager.current_session = session("Sway");
+    manager.username = "alice".into();
+    manager.password = "hunter2".into();
+    manager.mode = Mode::EditingPassword;
+    manager.render().unwrap();
+    drop(manager);
+    let _ = std::fs::remove_dir_all(&dir);
+    assert_snapshot("native_theme_widgets", &mut output);
+}
@@ -0,0 +1,226 @@
+//! A scripted stand-in for greetd, speaking `greetd_ipc` on a temporary
+//! Unix socket so the login flow can run under `cargo test`.
+
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+
+use greetd_ipc::{AuthMessageType, ErrorType, Request, Response, codec::SyncCodec};
+
+/// The request a script step waits for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expect {
+    CreateSession(String),
+    Answer(Option<String>),
+    StartSession(Vec<String>),
+}
+
+/// One exchange of a conversation: the request greetd waits for and the
+/// response it sends back.
+pub struct Step {
+    expect: Expect,
+    reply: Response,
+}
+
+impl Step {
+    pub fn new(expect: Expect, reply: Response) -> Self {
+        Self { expect, reply }
+    }
+}
+
+pub fn secret(text: &str) -> Response {
+    Response::AuthMessage {
+        auth_message_type: AuthMessageType::Secret,
+        auth_message: text.to_string(),
+    }
+}
+
+pub fn visible(text: &str) -> Response {
+    Response::AuthMessage {
+        auth_message_type: AuthMessageType::Visible,
+        auth_message: text.to_string(),
+    }
+}
+
+pub fn info(text: &str) -> Response {
+    Response::AuthMessage {
+        auth_message_type: AuthMessageType::Info,
+        auth_message: text.to_string(),
+    }
+}
+
+pub fn pam_error(text: &str) -> Response {
+    Response::AuthMessage {
+        auth_message_type: AuthMessageType::Error,
+        auth_message: text.to_string(),
+    }
+}
+
+pub fn auth_error(description: &str) -> Response {
+    Response::Error {
+        error_type: ErrorType::AuthError,
+        description: description.to_string(),
+    }
+}
+
+pub fn error(description: &str) -> Response {
+    Response::Error {
+        error_type: ErrorType::Error,
+        description: description.to_string(),
+    }
+}
+
+fn answer(text: &str) -> Expect {
+    Expect::Answer(Some(text.to_string()))
+}
+
+/// Ready-made conversations for a user `alice` with password `hunter2`.
+pub mod script {
+    use super::*;
+
+    pub fn success(cmd: &[&str]) -> Vec<Step> {
+        vec![
+            Step::new(Expect::CreateSession("alice".into()), secret("Password: ")),
+            Step::new(answer("hunter2"), Response::Success),
+            Step::new(start(cmd), Response::Success),
+        ]
+    }
+
+    pub fn wrong_password() -> Vec<Step> {
+        vec![
+            Step::new(Expect::CreateSession("alice".into()), secret("Password: ")),
+            Step::new(answer("hunter2"), auth_error("Authentication failed")),
+        ]
+    }
+
+    /// Password followed by a one-time code.
+    pub fn multi_prompt(cmd: &[&str]) -> Vec<Step> {
+        vec![
+            Step::new(Expect::CreateSession("alice".into()), secret("Password: ")),
+            Step::new(answer("hunter2"), visible("Verification code: ")),
+            Step::new(answer("123456"), Response::Success),
+            Step::new(start(cmd), Response::Success),
+        ]
+    }
+
+    /// A second secret prompt from a token, worded with "new" and "hold",
+    /// without the password having expired.
+    pub fn token_code(cmd: &[&str]) -> Vec<Step> {
+        vec![
+            Step::new(Expect::CreateSession("alice".into()), secret("Password: ")),
+            Step::new(
+                answer("hunter2"),
+                secret("Hold the key to get a new code: "),
+            ),
+            Step::new(answer("123456"), Response::Success),
+            Step::new(start(cmd), Response::Success),
+        ]
+    }
+
+    /// An expired password, where the first current password given on the
+    /// change screen is rejected.
+    pub fn current_password_rejected(cmd: &[&str]) -> Vec<Step> {
+        vec![
+            Step::new(Expect::CreateSession("alice".into()), secret("Password: ")),
+            Step::new(
+                answer("hunter2"),
+                pam_error("You are required to change your password immediately"),
+            ),
+            Step::new(Expect::Answer(None), secret("Current password: ")),
+            Step::new(answer("hunter2"), secret("Current password: ")),
+            Step::new(answer("hunter3"), secret("New password: ")),
+            Step::new(answer("s3cret"), secret("Retype new password: ")),
+            Step::new(answer("s3cret"), Response::Success),
+            Step::new(start(cmd), Response::Success),
+        ]
+    }
+
+    /// No questions at all, just a notice to acknowledge.
+    pub fn info_only(cmd: &[&str]) -> Vec<Step> {
+        vec![
+            Step::new(Expect::CreateSession("alice".into()), info("Welcome back")),
+            Step::new(Expect::Answer(None), Response::Success),
+            Step::new(start(cmd), Response::Success),
+        ]
+    }
+
+    pub fn start_session_failure(cmd: &[&str]) -> Vec<Step> {
+        vec![
+            Step::new(Expect::CreateSession("alice".into()), secret("Password: ")),
+            Step::new(answer("hunter2"), Response::Success),
+            Step::new(start(cmd), error("command not found")),
+        ]
+    }
+
+    fn start(cmd: &[&str]) -> Expect {
+        Expect::StartSession(cmd.iter().map(|s| s.to_string()).collect())
+    }
+}
+
+/// A greetd server following a script on its own thread.
+///
+/// `CancelSession` is always answered with success and doesn't consume a
+/// script step, as greetd accepts it at any point of a conversation.
+pub struct MockGreetd {
+    path: PathBuf,
+    handle: JoinHandle<Result<(), String>>,
+}
+
+impl MockGreetd {
+    pub fn spawn(script: Vec<Step>) -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "ndlm-greetd-{}-{}.sock",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).expect("could not bind mock greetd socket");
+        let handle = thread::spawn(move || serve(listener, script));
+        Self { path, handle }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Waits for the client to hang up and reports whether the whole script
+    /// ran as expected.
+    pub fn finish(self) -> Result<(), String> {
+        let result = self.handle.join().expect("mock greetd panicked");
+        let _ = std::fs::remove_file(&self.path);
+        result
+    }
+}
+
+fn serve(listener: UnixListener, script: Vec<Step>) -> Result<(), String> {
+    let (mut stream, _) = listener.accept().map_err(|e| e.to_string())?;
+    let mut steps = script.into_iter();
+    while let Ok(request) = Request::read_from(&mut stream) {
+        let received = match request {
+            Request::CancelSession => {
+                let _ = Response::Success.write_to(&mut stream);
+                continue;
+            }
+            Request::CreateSession { username } => Expect::CreateSession(username),
+            Request::PostAuthMessageResponse { response } => Expect::Answer(response),
+            Request::StartSession { cmd, .. } => Expect::StartSession(cmd),
+        };
+        let Some(step) = steps.next() else {
+            let _ = error("script finished").write_to(&mut stream);
+            return Err(format!("unexpected {received:?} after the script ended"));
+        };
+        if step.expect != received {
+            let _ = error("unexpected request").write_to(&mut stream);
+            return Err(format!("expected {:?}, got {received:?}", step.expect));
+        }
+        step.reply
+            .write_to(&mut stream)
+            .map_err(|e| e.to_string())?;
+    }
+    match steps.next() {
+        Some(step) => Err(format!("client left before {:?}", step.expect)),
+        None => Ok(()),
+    }
+}
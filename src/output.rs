@@ -0,0 +1,426 @@
+use std::cell::RefCell;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+
+use cairo::{Format, ImageSurface};
+use drm::buffer::Buffer;
+use drm::control::Device as ControlDevice;
+use drm::control::dumbbuffer::DumbBuffer;
+use drm::control::{Event, PageFlipFlags, crtc, framebuffer};
+use drm_fourcc::DrmFourcc;
+use libc::{POLLIN, POLLPRI, poll, pollfd};
+
+use crate::display::Display;
+use crate::draw::DrawError;
+
+pub struct Card {
+    file: std::fs::File,
+    /// CRTCs whose page flip completed and that no output has seen yet.
+    /// Events for every output arrive on the one card descriptor.
+    flipped: RefCell<Vec<crtc::Handle>>,
+}
+
+impl Card {
+    pub fn new(file: std::fs::File) -> Self {
+        Self {
+            file,
+            flipped: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Waits up to `timeout` milliseconds, or forever if negative, for DRM
+    /// events and notes the completed page flips.
+    fn wait_for_events(&self, timeout: i32) -> std::io::Result<()> {
+        let mut fds = [pollfd {
+            fd: self.as_raw_fd(),
+            events: (POLLIN | POLLPRI),
+            revents: 0,
+        }];
+        let res = unsafe { poll(fds.as_mut_ptr(), 1, timeout) };
+        if res < 0 {
+            let err = std::io::Error::last_os_error();
+            return match err.kind() {
+                std::io::ErrorKind::Interrupted => Ok(()),
+                _ => Err(err),
+            };
+        }
+        if (fds[0].revents & (POLLIN | POLLPRI)) != 0 {
+            for event in self.receive_events()? {
+                if let Event::PageFlip(flip) = event {
+                    self.flipped.borrow_mut().push(flip.crtc);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops completed flips of `crtc` no one waited for, including events
+    /// not read yet, so they aren't taken for the next flip's.
+    fn forget_flips(&self, crtc: crtc::Handle) {
+        let _ = self.wait_for_events(0);
+        self.flipped.borrow_mut().retain(|&c| c != crtc);
+    }
+
+    /// Whether a page flip on `crtc` completed, forgetting it if so.
+    fn take_flip(&self, crtc: crtc::Handle) -> bool {
+        let mut flipped = self.flipped.borrow_mut();
+        match flipped.iter().position(|&c| c == crtc) {
+            Some(index) => {
+                flipped.swap_remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl std::os::unix::io::AsFd for Card {
+    fn as_fd(&self) -> std::os::unix::io::BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+}
+
+impl std::os::unix::io::AsRawFd for Card {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl drm::Device for Card {}
+impl ControlDevice for Card {}
+
+/// Destination of rendered frames.
+///
+/// The buffer is XRGB8888 with rows `pitch()` bytes apart, which drivers
+/// may pad beyond `size().0 * 4`; everything in `draw` renders into it
+/// without knowing where it ends up.
+pub trait Output {
+    fn size(&self) -> (u32, u32);
+    /// Bytes from the start of one row to the next.
+    fn pitch(&self) -> u32;
+    fn buffer(&mut self) -> &mut [u8];
+    /// Shows the frame that was drawn into `buffer`.
+    fn present(&mut self) -> Result<(), crate::Error>;
+    /// Descriptor that becomes readable when the page flip started by the
+    /// last `present` completes, while one is in flight. Outputs that show
+    /// frames at once have none.
+    fn flip_fd(&self) -> Option<RawFd> {
+        None
+    }
+}
+
+impl<T: Output + ?Sized> Output for &mut T {
+    fn size(&self) -> (u32, u32) {
+        (**self).size()
+    }
+
+    fn pitch(&self) -> u32 {
+        (**self).pitch()
+    }
+
+    fn buffer(&mut self) -> &mut [u8] {
+        (**self).buffer()
+    }
+
+    fn present(&mut self) -> Result<(), crate::Error> {
+        (**self).present()
+    }
+    fn flip_fd(&self) -> Option<RawFd> {
+        (**self).flip_fd()
+    }
+}
+
+/// A dumb buffer with its framebuffer, mapped for drawing. Everything is
+/// released again on drop.
+struct ScanoutBuffer<'a> {
+    card: &'a Card,
+    buffer: DumbBuffer,
+    fb: framebuffer::Handle,
+    map: *mut u8,
+    len: usize,
+}
+
+impl<'a> ScanoutBuffer<'a> {
+    fn new(card: &'a Card, size: (u32, u32)) -> std::io::Result<Self> {
+        let buffer = card.create_dumb_buffer(size, DrmFourcc::Xrgb8888, 32)?;
+        let fb = match card.add_framebuffer(&buffer, 24, 32) {
+            Ok(fb) => fb,
+            Err(err) => {
+                let _ = card.destroy_dumb_buffer(buffer);
+                return Err(err);
+            }
+        };
+        // The mapping is kept for as long as the buffer and unmapped in
+        // `drop`, rather than borrowing the buffer.
+        let (map, len) = match card.map_dumb_buffer(&mut buffer.clone()) {
+            Ok(mut mapping) => {
+                let map = (mapping.as_mut_ptr(), mapping.len());
+                std::mem::forget(mapping);
+                map
+            }
+            Err(err) => {
+                let _ = card.destroy_framebuffer(fb);
+                let _ = card.destroy_dumb_buffer(buffer);
+                return Err(err);
+            }
+        };
+        Ok(Self {
+            card,
+            buffer,
+            fb,
+            map,
+            len,
+        })
+    }
+
+    fn as_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.map, self.len) }
+    }
+}
+
+impl Drop for ScanoutBuffer<'_> {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.map.cast(), self.len) };
+        let _ = self.card.destroy_framebuffer(self.fb);
+        let _ = self.card.destroy_dumb_buffer(self.buffer);
+    }
+}
+
+/// Buffers allocated per output; with a third one, the next frame can be
+/// drawn while a flip is still in flight.
+const SWAPCHAIN_LEN: usize = 3;
+
+/// Which buffers of a swapchain are scanned out, being flipped to, or free
+/// to draw into.
+#[derive(Debug)]
+struct Swapchain {
+    len: usize,
+    /// The buffer on screen.
+    front: usize,
+    /// The buffer flipped to, until the flip completes.
+    pending: Option<usize>,
+}
+
+impl Swapchain {
+    fn new(len: usize) -> Self {
+        Self {
+            len,
+            front: 0,
+            pending: None,
+        }
+    }
+
+    /// The buffer to draw the next frame into, the first after the front
+    /// one that is neither on screen nor being flipped to.
+    fn back(&self) -> Option<usize> {
+        (1..self.len)
+            .map(|i| (self.front + i) % self.len)
+            .find(|&i| Some(i) != self.pending)
+    }
+
+    fn flipping(&mut self, index: usize) {
+        self.pending = Some(index);
+    }
+
+    /// The pending buffer is on screen; the one it replaced is free again.
+    fn flipped(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            self.front = pending;
+        }
+    }
+}
+
+/// Scans frames out of a swapchain of mapped DRM dumb buffers.
+///
+/// Frames are drawn into a back buffer and flipped to, so the buffer on
+/// screen is never drawn into. Buffers are recycled once the kernel reports
+/// the flip away from them complete.
+pub struct DrmOutput<'a> {
+    card: &'a Card,
+    buffers: Vec<ScanoutBuffer<'a>>,
+    swapchain: Swapchain,
+    size: (u32, u32),
+    crtc: crtc::Handle,
+}
+
+impl<'a> DrmOutput<'a> {
+    /// Allocates buffers the size of the display's mode and shows the first
+    /// one on the display.
+    pub fn new(card: &'a Card, display: &Display) -> std::io::Result<Self> {
+        let (width, height) = display.mode.size();
+        let size = (width as u32, height as u32);
+        let mut buffers = vec![
+            ScanoutBuffer::new(card, size)?,
+            ScanoutBuffer::new(card, size)?,
+        ];
+        while buffers.len() < SWAPCHAIN_LEN {
+            match ScanoutBuffer::new(card, size) {
+                Ok(buffer) => buffers.push(buffer),
+                Err(err) => {
+                    eprintln!("{}: double buffering only, {err}", display.name);
+                    break;
+                }
+            }
+        }
+        card.set_crtc(
+            display.crtc,
+            Some(buffers[0].fb),
+            (0, 0),
+            &[display.connector],
+            Some(display.mode),
+        )?;
+        Ok(Self {
+            card,
+            swapchain: Swapchain::new(buffers.len()),
+            buffers,
+            size,
+            crtc: display.crtc,
+        })
+    }
+
+    /// Waits for the flip in flight, if any, to complete.
+    fn wait_for_flip(&mut self) {
+        while self.swapchain.pending.is_some() && !self.card.take_flip(self.crtc) {
+            if let Err(err) = self.card.wait_for_events(-1) {
+                // Better to risk a torn frame than to hang.
+                eprintln!("could not wait for page flip: {err}");
+                break;
+            }
+        }
+        self.swapchain.flipped();
+    }
+
+    /// The buffer to draw into, waiting for one to be free.
+    fn back(&mut self) -> usize {
+        match self.swapchain.back() {
+            Some(back) => back,
+            None => {
+                self.wait_for_flip();
+                self.swapchain.back().unwrap_or(self.swapchain.front)
+            }
+        }
+    }
+}
+
+impl Output for DrmOutput<'_> {
+    fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    fn pitch(&self) -> u32 {
+        self.buffers[0].buffer.pitch()
+    }
+
+    fn buffer(&mut self) -> &mut [u8] {
+        let back = self.back();
+        self.buffers[back].as_mut()
+    }
+
+    /// Flips to the frame drawn into the back buffer. Only one flip can be
+    /// in flight, so this waits for the previous one.
+    fn present(&mut self) -> Result<(), crate::Error> {
+        let back = self.back();
+        self.wait_for_flip();
+        // A flip given up on above may still report in; it must not count
+        // as this one completing.
+        self.card.forget_flips(self.crtc);
+        self.card
+            .page_flip(self.crtc, self.buffers[back].fb, PageFlipFlags::EVENT, None)?;
+        self.swapchain.flipping(back);
+        Ok(())
+    }
+    fn flip_fd(&self) -> Option<RawFd> {
+        self.swapchain.pending.map(|_| self.card.as_raw_fd())
+    }
+}
+
+impl Drop for DrmOutput<'_> {
+    fn drop(&mut self) {
+        // The buffers must not be freed while being flipped to.
+        self.wait_for_flip();
+    }
+}
+
+/// Keeps frames in memory, for `--render-to` and tests.
+pub struct HeadlessOutput {
+    data: Vec<u8>,
+    size: (u32, u32),
+    pitch: u32,
+}
+
+impl HeadlessOutput {
+    pub fn new(size: (u32, u32)) -> Self {
+        Self::with_pitch(size, size.0 * 4)
+    }
+
+    /// An output with rows padded to `pitch` bytes, like some drivers'
+    /// dumb buffers.
+    pub fn with_pitch(size: (u32, u32), pitch: u32) -> Self {
+        let len = (pitch as usize)
+            .checked_mul(size.1 as usize)
+            .expect("headless output too large");
+        Self {
+            data: vec![0; len],
+            size,
+            pitch,
+        }
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<(), crate::Error> {
+        let (width, height) = (self.size.0 as i32, self.size.1 as i32);
+        let surface = ImageSurface::create_for_data(
+            self.data.clone(),
+            Format::Rgb24,
+            width,
+            height,
+            self.pitch as i32,
+        )
+        .map_err(DrawError::from)?;
+        let mut file = std::fs::File::create(path)?;
+        surface.write_to_png(&mut file).map_err(DrawError::from)?;
+        Ok(())
+    }
+}
+
+impl Output for HeadlessOutput {
+    fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    fn pitch(&self) -> u32 {
+        self.pitch
+    }
+
+    fn buffer(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    fn present(&mut self) -> Result<(), crate::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffers_are_recycled_once_flipped_away_from() {
+        let mut double = Swapchain::new(2);
+        assert_eq!(double.back(), Some(1));
+        double.flipping(1);
+        // Buffer 0 is on screen and 1 is being flipped to: none is free.
+        assert_eq!(double.back(), None);
+        double.flipped();
+        assert_eq!((double.front, double.back()), (1, Some(0)));
+
+        let mut triple = Swapchain::new(3);
+        triple.flipping(1);
+        assert_eq!(triple.back(), Some(2));
+        triple.flipped();
+        triple.flipping(2);
+        assert_eq!(triple.back(), Some(0));
+        triple.flipped();
+        assert_eq!((triple.front, triple.pending), (2, None));
+    }
+}
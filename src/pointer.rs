@@ -0,0 +1,54 @@
+//! Mouse motion read straight from evdev, so the dialog can move to the
+//! screen under the pointer without a display server.
+
+use std::io::ErrorKind;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use evdev::{Device, EventSummary, RelativeAxisCode};
+
+pub struct Pointer {
+    devices: Vec<Device>,
+}
+
+impl Pointer {
+    /// Opens every device that reports horizontal relative motion. Devices
+    /// the greeter is not allowed to read are left out.
+    pub fn open() -> Self {
+        let devices = evdev::enumerate()
+            .map(|(_, device)| device)
+            .filter(|device| {
+                device
+                    .supported_relative_axes()
+                    .is_some_and(|axes| axes.contains(RelativeAxisCode::REL_X))
+            })
+            .filter(|device| device.set_nonblocking(true).is_ok())
+            .collect();
+        Self { devices }
+    }
+
+    pub fn fds(&self) -> Vec<RawFd> {
+        self.devices.iter().map(Device::as_raw_fd).collect()
+    }
+
+    /// Horizontal motion since the last call, in device units. Devices
+    /// that fail to read, as an unplugged mouse does with `ENODEV`, are
+    /// dropped so their hangup does not keep waking the poll.
+    pub fn motion(&mut self) -> i32 {
+        let mut dx = 0;
+        self.devices
+            .retain_mut(|device| match device.fetch_events() {
+                Ok(events) => {
+                    for event in events {
+                        if let EventSummary::RelativeAxis(_, RelativeAxisCode::REL_X, value) =
+                            event.destructure()
+                        {
+                            dx += value;
+                        }
+                    }
+                    true
+                }
+                Err(err) => err.kind() == ErrorKind::WouldBlock,
+            });
+        dx
+    }
+}
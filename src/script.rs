@@ -0,0 +1,218 @@
+//! Themes written for plymouth's script plugin.
+//!
+//! Only the part of the language and builtins that static layouts need is
+//! implemented: `Image`, `Sprite`, `Window`, `Math` and the `Plymouth`
+//! callbacks for refreshing and for the password and question prompts.
+
+mod interpreter;
+mod lexer;
+mod parser;
+
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::color::Color;
+use crate::draw::FramebufferSurface;
+use interpreter::{Interpreter, Value};
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum ScriptError {
+    #[error("could not read {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("line {line}: {message}")]
+    Parse { line: usize, message: String },
+    #[error("{0}")]
+    Runtime(String),
+}
+
+impl ScriptError {
+    fn parse(line: usize, message: impl Into<String>) -> Self {
+        ScriptError::Parse {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+/// A loaded `.script` theme and the sprites it has put on screen.
+pub struct ScriptTheme {
+    interpreter: Interpreter,
+}
+
+impl ScriptTheme {
+    /// Parses `script_file` and runs its top level, which sets up sprites
+    /// and registers callbacks. Images are looked up in `image_dir`.
+    pub fn load(
+        script_file: &Path,
+        image_dir: &Path,
+        window_size: (u32, u32),
+    ) -> Result<Self, ScriptError> {
+        let source = std::fs::read_to_string(script_file).map_err(|source| ScriptError::Io {
+            path: script_file.to_path_buf(),
+            source,
+        })?;
+        Self::from_source(&source, image_dir, window_size)
+    }
+
+    pub fn from_source(
+        source: &str,
+        image_dir: &Path,
+        window_size: (u32, u32),
+    ) -> Result<Self, ScriptError> {
+        let program = parser::parse(source)?;
+        let mut interpreter = Interpreter::new(image_dir.to_path_buf(), window_size);
+        interpreter.run(&program)?;
+        Ok(Self { interpreter })
+    }
+
+    pub fn has_refresh(&self) -> bool {
+        self.interpreter.callbacks.refresh.is_some()
+    }
+
+    pub fn has_message(&self) -> bool {
+        self.interpreter.callbacks.message.is_some()
+    }
+
+    /// Calls the function given to `Plymouth.SetRefreshFunction`.
+    pub fn refresh(&mut self) -> Result<(), ScriptError> {
+        self.callback(|c| &c.refresh, Vec::new())
+    }
+
+    /// Shows a secret prompt with `bullets` characters typed; returns false
+    /// if the theme has no password callback and the prompt is left to us.
+    pub fn display_password(&mut self, prompt: &str, bullets: usize) -> Result<bool, ScriptError> {
+        let args = vec![Value::Str(prompt.into()), Value::Number(bullets as f64)];
+        self.handled(|c| &c.display_password, args)
+    }
+
+    /// Shows a visible prompt with the text typed so far, like
+    /// `display_password`.
+    pub fn display_question(&mut self, prompt: &str, entry: &str) -> Result<bool, ScriptError> {
+        let args = vec![Value::Str(prompt.into()), Value::Str(entry.into())];
+        self.handled(|c| &c.display_question, args)
+    }
+
+    /// Tells the theme no prompt is shown anymore.
+    pub fn display_normal(&mut self) -> Result<(), ScriptError> {
+        self.callback(|c| &c.display_normal, Vec::new())
+    }
+
+    pub fn display_message(&mut self, text: &str) -> Result<(), ScriptError> {
+        self.callback(|c| &c.message, vec![Value::Str(text.into())])
+    }
+
+    /// The top and bottom colors set through `Window`, if any.
+    pub fn background(&self) -> Option<(Color, Color)> {
+        let top = self.interpreter.background_top?;
+        Some((top, self.interpreter.background_bottom.unwrap_or(top)))
+    }
+
+    /// Draws the live sprites from the lowest `z` up.
+    pub fn draw(&mut self, surf: &FramebufferSurface) {
+        for sprite in self.interpreter.sprites() {
+            let sprite = sprite.borrow();
+            if let Some(image) = &sprite.image {
+                let (x, y) = (sprite.x.round() as i32, sprite.y.round() as i32);
+                surf.draw_image_with_opacity(image, x, y, sprite.opacity);
+            }
+        }
+    }
+
+    fn handled(
+        &mut self,
+        slot: impl Fn(&interpreter::Callbacks) -> &Option<Value>,
+        args: Vec<Value>,
+    ) -> Result<bool, ScriptError> {
+        let handled = slot(&self.interpreter.callbacks).is_some();
+        self.callback(slot, args)?;
+        Ok(handled)
+    }
+
+    fn callback(
+        &mut self,
+        slot: impl Fn(&interpreter::Callbacks) -> &Option<Value>,
+        args: Vec<Value>,
+    ) -> Result<(), ScriptError> {
+        match slot(&self.interpreter.callbacks).clone() {
+            Some(function) => self.interpreter.call_callback(&function, args),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str) -> Result<ScriptTheme, ScriptError> {
+        ScriptTheme::from_source(source, Path::new("/nonexistent"), (800, 600))
+    }
+
+    fn positions(theme: &mut ScriptTheme) -> Vec<(f64, f64, f64)> {
+        let sprites = theme.interpreter.sprites();
+        let positions = sprites
+            .iter()
+            .map(|s| s.borrow())
+            .map(|s| (s.x, s.y, s.opacity));
+        positions.collect()
+    }
+
+    #[test]
+    fn functions_hashes_and_sprites() {
+        let mut theme = run(r#"
+            fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+            list[0] = "a"; list[1] = "b";
+            label = list[0] + list[1] + 3;
+            s = Sprite();
+            s.SetPosition(fib(10), Math.Int(Window.GetWidth() / 3), 1);
+            if (label == "ab3") s.SetOpacity(0.5);
+            Sprite().SetX(1); # dropped right away
+        "#)
+        .unwrap();
+        assert_eq!(positions(&mut theme), [(55.0, 266.0, 0.5)]);
+    }
+
+    #[test]
+    fn password_callback_places_bullets() {
+        let mut theme = run(r#"
+            Plymouth.SetDisplayPasswordFunction(fun (prompt, bullets) {
+                global.bullets = NULL;
+                for (i = 0; i < bullets; i++) {
+                    global.bullets[i] = Sprite();
+                    global.bullets[i].SetX(i * 10);
+                }
+            });
+            Plymouth.SetDisplayNormalFunction(fun () { global.bullets = NULL; });
+        "#)
+        .unwrap();
+        assert!(theme.display_password("Password:", 3).unwrap());
+        assert_eq!(positions(&mut theme).len(), 3);
+        assert!(!theme.display_question("Username:", "alice").unwrap());
+        theme.display_normal().unwrap();
+        assert!(positions(&mut theme).is_empty());
+    }
+
+    #[test]
+    fn missing_builtins_return_null() {
+        let mut theme = run(r#"
+            Plymouth.SetBootProgressFunction(fun (duration, progress) { x = progress; });
+            Plymouth.SetQuitFunction(fun () { x = 0; });
+            status = Plymouth.GetStatus();
+            s = Sprite();
+            if (!status) s.SetX(7);
+        "#)
+        .unwrap();
+        assert_eq!(positions(&mut theme), [(7.0, 0.0, 1.0)]);
+    }
+
+    #[test]
+    fn runaway_scripts_are_stopped() {
+        let err = run("while (1) x++;").err().unwrap();
+        assert_eq!(err.to_string(), "script ran for too long");
+    }
+}
@@ -0,0 +1,820 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::{Rc, Weak};
+
+use cairo::{Context as CairoContext, Format, ImageSurface};
+use pango::FontDescription;
+
+use super::ScriptError;
+use super::parser::{BinOp, Expr, FunDef, Stmt, UnOp};
+use crate::color::Color;
+use crate::draw::{load_png, render_text};
+
+/// Statements a single run may execute, so a runaway loop in a theme can
+/// not lock up the login screen.
+const MAX_STEPS: usize = 1_000_000;
+const MAX_DEPTH: usize = 256;
+
+pub type Hash = Rc<RefCell<HashMap<String, Value>>>;
+
+#[derive(Clone, Default)]
+pub enum Value {
+    #[default]
+    Null,
+    Number(f64),
+    Str(Rc<str>),
+    Hash(Hash),
+    Fun(Function),
+    Image(Rc<ImageSurface>),
+    Sprite(Rc<RefCell<Sprite>>),
+}
+
+#[derive(Clone)]
+pub enum Function {
+    Script(Rc<FunDef>),
+    Native(Native),
+}
+
+/// A positioned image; sprites are drawn by `z` order for as long as the
+/// script holds on to them.
+pub struct Sprite {
+    pub image: Option<Rc<ImageSurface>>,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub opacity: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Native {
+    ImageNew,
+    ImageText,
+    ImageGetWidth,
+    ImageGetHeight,
+    ImageScale,
+    ImageRotate,
+    ImageTile,
+    SpriteNew,
+    SpriteSetImage,
+    SpriteGetImage,
+    SpriteSetX,
+    SpriteSetY,
+    SpriteSetZ,
+    SpriteGetX,
+    SpriteGetY,
+    SpriteGetZ,
+    SpriteSetPosition,
+    SpriteSetOpacity,
+    SpriteGetOpacity,
+    WindowGetWidth,
+    WindowGetHeight,
+    WindowGetX,
+    WindowGetY,
+    WindowSetBackgroundTopColor,
+    WindowSetBackgroundBottomColor,
+    SetRefreshFunction,
+    SetDisplayPasswordFunction,
+    SetDisplayQuestionFunction,
+    SetDisplayNormalFunction,
+    SetMessageFunction,
+    GetMode,
+    MathAbs,
+    MathMin,
+    MathMax,
+    MathSin,
+    MathCos,
+    MathSqrt,
+    MathInt,
+    MathRandom,
+}
+
+/// Builtin objects and the natives they hold, as `Object.Name`.
+const NATIVES: &[(&str, &str, Native)] = &[
+    ("Image", "_New", Native::ImageNew),
+    ("Image", "Text", Native::ImageText),
+    ("Image", "GetWidth", Native::ImageGetWidth),
+    ("Image", "GetHeight", Native::ImageGetHeight),
+    ("Image", "Scale", Native::ImageScale),
+    ("Image", "Rotate", Native::ImageRotate),
+    ("Image", "Tile", Native::ImageTile),
+    ("Sprite", "_New", Native::SpriteNew),
+    ("Sprite", "SetImage", Native::SpriteSetImage),
+    ("Sprite", "GetImage", Native::SpriteGetImage),
+    ("Sprite", "SetX", Native::SpriteSetX),
+    ("Sprite", "SetY", Native::SpriteSetY),
+    ("Sprite", "SetZ", Native::SpriteSetZ),
+    ("Sprite", "GetX", Native::SpriteGetX),
+    ("Sprite", "GetY", Native::SpriteGetY),
+    ("Sprite", "GetZ", Native::SpriteGetZ),
+    ("Sprite", "SetPosition", Native::SpriteSetPosition),
+    ("Sprite", "SetOpacity", Native::SpriteSetOpacity),
+    ("Sprite", "GetOpacity", Native::SpriteGetOpacity),
+    ("Window", "GetWidth", Native::WindowGetWidth),
+    ("Window", "GetHeight", Native::WindowGetHeight),
+    ("Window", "GetX", Native::WindowGetX),
+    ("Window", "GetY", Native::WindowGetY),
+    (
+        "Window",
+        "SetBackgroundTopColor",
+        Native::WindowSetBackgroundTopColor,
+    ),
+    (
+        "Window",
+        "SetBackgroundBottomColor",
+        Native::WindowSetBackgroundBottomColor,
+    ),
+    ("Plymouth", "SetRefreshFunction", Native::SetRefreshFunction),
+    (
+        "Plymouth",
+        "SetDisplayPasswordFunction",
+        Native::SetDisplayPasswordFunction,
+    ),
+    (
+        "Plymouth",
+        "SetDisplayQuestionFunction",
+        Native::SetDisplayQuestionFunction,
+    ),
+    (
+        "Plymouth",
+        "SetDisplayNormalFunction",
+        Native::SetDisplayNormalFunction,
+    ),
+    ("Plymouth", "SetMessageFunction", Native::SetMessageFunction),
+    ("Plymouth", "GetMode", Native::GetMode),
+    ("Math", "Abs", Native::MathAbs),
+    ("Math", "Min", Native::MathMin),
+    ("Math", "Max", Native::MathMax),
+    ("Math", "Sin", Native::MathSin),
+    ("Math", "Cos", Native::MathCos),
+    ("Math", "Sqrt", Native::MathSqrt),
+    ("Math", "Int", Native::MathInt),
+    ("Math", "Random", Native::MathRandom),
+];
+
+/// Functions the theme registered through the `Plymouth` object.
+#[derive(Default)]
+pub struct Callbacks {
+    pub refresh: Option<Value>,
+    pub display_password: Option<Value>,
+    pub display_question: Option<Value>,
+    pub display_normal: Option<Value>,
+    pub message: Option<Value>,
+}
+
+enum Flow {
+    Normal,
+    Return(Value),
+    Break,
+    Continue,
+}
+
+struct Frame {
+    local: Hash,
+    this: Value,
+}
+
+pub struct Interpreter {
+    global: Hash,
+    frames: Vec<Frame>,
+    sprites: Vec<Weak<RefCell<Sprite>>>,
+    image_dir: PathBuf,
+    window_size: (u32, u32),
+    pub callbacks: Callbacks,
+    pub background_top: Option<Color>,
+    pub background_bottom: Option<Color>,
+    steps: usize,
+    random: u64,
+}
+
+fn new_hash() -> Hash {
+    Rc::new(RefCell::new(HashMap::new()))
+}
+
+impl Value {
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Number(n) => *n != 0.0,
+            Value::Str(s) => !s.is_empty(),
+            _ => true,
+        }
+    }
+
+    fn number(&self) -> f64 {
+        match self {
+            Value::Number(n) => *n,
+            Value::Str(s) => s.trim().parse().unwrap_or(0.0),
+            _ => 0.0,
+        }
+    }
+
+    /// Text of numbers and strings, as used for hash keys and `+`.
+    fn text(&self) -> Option<String> {
+        match self {
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => {
+                Some(format!("{}", *n as i64))
+            }
+            Value::Number(n) => Some(n.to_string()),
+            Value::Str(s) => Some(s.to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl Interpreter {
+    pub fn new(image_dir: PathBuf, window_size: (u32, u32)) -> Self {
+        let global = new_hash();
+        {
+            let mut globals = global.borrow_mut();
+            for (object, name, native) in NATIVES {
+                let entry = globals
+                    .entry(object.to_string())
+                    .or_insert_with(|| Value::Hash(new_hash()));
+                if let Value::Hash(hash) = entry {
+                    hash.borrow_mut()
+                        .insert(name.to_string(), Value::Fun(Function::Native(*native)));
+                }
+            }
+            if let Some(Value::Hash(math)) = globals.get("Math") {
+                math.borrow_mut()
+                    .insert("Pi".into(), Value::Number(std::f64::consts::PI));
+            }
+        }
+        Self {
+            frames: vec![Frame {
+                local: global.clone(),
+                this: Value::Null,
+            }],
+            global,
+            sprites: Vec::new(),
+            image_dir,
+            window_size,
+            callbacks: Callbacks::default(),
+            background_top: None,
+            background_bottom: None,
+            steps: 0,
+            random: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    /// Runs top-level statements, where locals are globals.
+    pub fn run(&mut self, program: &[Stmt]) -> Result<(), ScriptError> {
+        self.steps = 0;
+        self.block(program)?;
+        Ok(())
+    }
+
+    /// Calls a function the theme registered, with a fresh step budget.
+    pub fn call_callback(&mut self, function: &Value, args: Vec<Value>) -> Result<(), ScriptError> {
+        self.steps = 0;
+        self.call(function, Value::Null, args)?;
+        Ok(())
+    }
+
+    /// Live sprites in drawing order.
+    pub fn sprites(&mut self) -> Vec<Rc<RefCell<Sprite>>> {
+        self.sprites.retain(|s| s.strong_count() > 0);
+        let mut sprites: Vec<_> = self.sprites.iter().filter_map(Weak::upgrade).collect();
+        sprites.sort_by(|a, b| a.borrow().z.total_cmp(&b.borrow().z));
+        sprites
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames
+            .last()
+            .expect("the top-level frame is never popped")
+    }
+
+    fn block(&mut self, statements: &[Stmt]) -> Result<Flow, ScriptError> {
+        for statement in statements {
+            match self.exec(statement)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec(&mut self, statement: &Stmt) -> Result<Flow, ScriptError> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err(ScriptError::Runtime("script ran for too long".into()));
+        }
+        match statement {
+            Stmt::Expr(expr) => {
+                self.eval(expr)?;
+            }
+            Stmt::Block(statements) => return self.block(statements),
+            Stmt::If(condition, then, otherwise) => {
+                if self.eval(condition)?.truthy() {
+                    return self.exec(then);
+                } else if let Some(otherwise) = otherwise {
+                    return self.exec(otherwise);
+                }
+            }
+            Stmt::While(condition, body) => {
+                while self.eval(condition)?.truthy() {
+                    match self.exec(body)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        _ => {}
+                    }
+                }
+            }
+            Stmt::For(init, condition, step, body) => {
+                if let Some(init) = init {
+                    self.eval(init)?;
+                }
+                loop {
+                    if let Some(condition) = condition
+                        && !self.eval(condition)?.truthy()
+                    {
+                        break;
+                    }
+                    match self.exec(body)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        _ => {}
+                    }
+                    if let Some(step) = step {
+                        self.eval(step)?;
+                    }
+                }
+            }
+            Stmt::Fun(name, def) => {
+                self.global
+                    .borrow_mut()
+                    .insert(name.clone(), Value::Fun(Function::Script(def.clone())));
+            }
+            Stmt::Return(value) => {
+                let value = match value {
+                    Some(expr) => self.eval(expr)?,
+                    None => Value::Null,
+                };
+                return Ok(Flow::Return(value));
+            }
+            Stmt::Break => return Ok(Flow::Break),
+            Stmt::Continue => return Ok(Flow::Continue),
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<Value, ScriptError> {
+        Ok(match expr {
+            Expr::Number(n) => Value::Number(*n),
+            Expr::Str(s) => Value::Str(s.clone()),
+            Expr::Var(name) => self.variable(name),
+            Expr::Member(base, name) => {
+                let base = self.eval(base)?;
+                self.member(&base, name)
+            }
+            Expr::Index(base, index) => {
+                let base = self.eval(base)?;
+                let key = self.key(index)?;
+                self.member(&base, &key)
+            }
+            Expr::Call(callee, args) => {
+                let (function, this) = match &**callee {
+                    Expr::Member(base, name) => {
+                        let this = self.eval(base)?;
+                        (self.member(&this, name), this)
+                    }
+                    callee => (self.eval(callee)?, Value::Null),
+                };
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.call(&function, this, args)?
+            }
+            Expr::Fun(def) => Value::Fun(Function::Script(def.clone())),
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand)?;
+                match op {
+                    UnOp::Not => Value::Number(if value.truthy() { 0.0 } else { 1.0 }),
+                    UnOp::Neg => Value::Number(-value.number()),
+                    UnOp::Plus => Value::Number(value.number()),
+                }
+            }
+            Expr::Binary(BinOp::And, left, right) => {
+                let left = self.eval(left)?;
+                if left.truthy() {
+                    self.eval(right)?
+                } else {
+                    left
+                }
+            }
+            Expr::Binary(BinOp::Or, left, right) => {
+                let left = self.eval(left)?;
+                if left.truthy() {
+                    left
+                } else {
+                    self.eval(right)?
+                }
+            }
+            Expr::Binary(op, left, right) => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                binary(*op, &left, &right)
+            }
+            Expr::Ternary(condition, then, otherwise) => {
+                if self.eval(condition)?.truthy() {
+                    self.eval(then)?
+                } else {
+                    self.eval(otherwise)?
+                }
+            }
+            Expr::Assign(op, target, value) => {
+                let mut value = self.eval(value)?;
+                if let Some(op) = op {
+                    let current = self.eval(target)?;
+                    value = binary(*op, &current, &value);
+                }
+                self.assign(target, value.clone())?;
+                value
+            }
+            Expr::Step {
+                target,
+                delta,
+                prefix,
+            } => {
+                let old = self.eval(target)?.number();
+                let new = Value::Number(old + delta);
+                self.assign(target, new.clone())?;
+                if *prefix { new } else { Value::Number(old) }
+            }
+        })
+    }
+
+    fn key(&mut self, index: &Expr) -> Result<String, ScriptError> {
+        let index = self.eval(index)?;
+        index
+            .text()
+            .ok_or_else(|| ScriptError::Runtime("index must be a number or string".into()))
+    }
+
+    fn variable(&self, name: &str) -> Value {
+        let frame = self.frame();
+        match name {
+            "global" => Value::Hash(self.global.clone()),
+            "local" => Value::Hash(frame.local.clone()),
+            "this" => frame.this.clone(),
+            _ => {
+                let local = frame.local.borrow().get(name).cloned();
+                local
+                    .or_else(|| self.global.borrow().get(name).cloned())
+                    .unwrap_or_default()
+            }
+        }
+    }
+
+    /// Looks up `name` on a value; images and sprites take their methods
+    /// from the global `Image` and `Sprite` objects.
+    fn member(&self, base: &Value, name: &str) -> Value {
+        let hash = match base {
+            Value::Hash(hash) => hash.clone(),
+            Value::Image(_) | Value::Sprite(_) => {
+                let object = if matches!(base, Value::Image(_)) {
+                    "Image"
+                } else {
+                    "Sprite"
+                };
+                match self.global.borrow().get(object) {
+                    Some(Value::Hash(hash)) => hash.clone(),
+                    _ => return Value::Null,
+                }
+            }
+            _ => return Value::Null,
+        };
+        let value = hash.borrow().get(name).cloned();
+        value.unwrap_or_default()
+    }
+
+    /// The hash behind an assignable expression, created on first use as
+    /// plymouth does for `a.b.c = 1` with `a` unset.
+    fn container(&mut self, expr: &Expr) -> Result<Hash, ScriptError> {
+        match self.eval(expr)? {
+            Value::Hash(hash) => Ok(hash),
+            Value::Null if matches!(expr, Expr::Var(_) | Expr::Member(..) | Expr::Index(..)) => {
+                let hash = new_hash();
+                self.assign(expr, Value::Hash(hash.clone()))?;
+                Ok(hash)
+            }
+            _ => Err(ScriptError::Runtime(
+                "cannot set a field on this value".into(),
+            )),
+        }
+    }
+
+    fn assign(&mut self, target: &Expr, value: Value) -> Result<(), ScriptError> {
+        match target {
+            Expr::Var(name) => {
+                let local = self.frame().local.clone();
+                let scope = if local.borrow().contains_key(name)
+                    || !self.global.borrow().contains_key(name)
+                {
+                    local
+                } else {
+                    self.global.clone()
+                };
+                scope.borrow_mut().insert(name.clone(), value);
+            }
+            Expr::Member(base, name) => {
+                let hash = self.container(base)?;
+                hash.borrow_mut().insert(name.clone(), value);
+            }
+            Expr::Index(base, index) => {
+                let key = self.key(index)?;
+                let hash = self.container(base)?;
+                hash.borrow_mut().insert(key, value);
+            }
+            _ => {
+                return Err(ScriptError::Runtime(
+                    "cannot assign to this expression".into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn call(
+        &mut self,
+        function: &Value,
+        this: Value,
+        args: Vec<Value>,
+    ) -> Result<Value, ScriptError> {
+        match function {
+            Value::Fun(Function::Script(def)) => {
+                if self.frames.len() > MAX_DEPTH {
+                    return Err(ScriptError::Runtime("recursion too deep".into()));
+                }
+                let local = new_hash();
+                {
+                    let mut locals = local.borrow_mut();
+                    for (i, param) in def.params.iter().enumerate() {
+                        locals.insert(param.clone(), args.get(i).cloned().unwrap_or_default());
+                    }
+                }
+                self.frames.push(Frame { local, this });
+                let flow = self.block(&def.body);
+                self.frames.pop();
+                Ok(match flow? {
+                    Flow::Return(value) => value,
+                    _ => Value::Null,
+                })
+            }
+            Value::Fun(Function::Native(native)) => self.native(*native, this, args),
+            // `Image("x.png")` and `Sprite()` construct through `_New`.
+            Value::Hash(hash) => {
+                let constructor = hash.borrow().get("_New").cloned();
+                match constructor {
+                    Some(constructor) => self.call(&constructor, function.clone(), args),
+                    None => Ok(Value::Null),
+                }
+            }
+            // Like plymouth, calling anything else, such as a builtin this
+            // interpreter lacks, gives NULL rather than stopping the theme.
+            _ => Ok(Value::Null),
+        }
+    }
+
+    fn native(
+        &mut self,
+        native: Native,
+        this: Value,
+        args: Vec<Value>,
+    ) -> Result<Value, ScriptError> {
+        let arg = |i: usize| args.get(i).cloned().unwrap_or_default();
+        let num = |i: usize| arg(i).number();
+        let image = match &this {
+            Value::Image(image) => Some(image.clone()),
+            _ => None,
+        };
+        let sprite = match &this {
+            Value::Sprite(sprite) => Some(sprite.clone()),
+            _ => None,
+        };
+        let color = || Color {
+            red: num(0) as f32,
+            green: num(1) as f32,
+            blue: num(2) as f32,
+            opacity: 1.0,
+        };
+        Ok(match native {
+            Native::ImageNew => match arg(0).text() {
+                Some(name) => load_png(&self.image_dir.join(name))
+                    .map_or(Value::Null, |image| Value::Image(Rc::new(image))),
+                None => Value::Null,
+            },
+            Native::ImageText => {
+                let text = arg(0).text().unwrap_or_default();
+                let color = Color {
+                    red: args.get(1).map_or(1.0, Value::number) as f32,
+                    green: args.get(2).map_or(1.0, Value::number) as f32,
+                    blue: args.get(3).map_or(1.0, Value::number) as f32,
+                    opacity: args.get(4).map_or(1.0, Value::number) as f32,
+                };
+                let font = arg(5).text().unwrap_or_else(|| "Sans 12".into());
+                let font = FontDescription::from_string(&font);
+                let image = render_text(&text, &font, &color)
+                    .map_err(|e| ScriptError::Runtime(e.to_string()))?;
+                Value::Image(Rc::new(image))
+            }
+            Native::ImageGetWidth => image.map_or(Value::Null, |i| Value::Number(i.width() as f64)),
+            Native::ImageGetHeight => {
+                image.map_or(Value::Null, |i| Value::Number(i.height() as f64))
+            }
+            Native::ImageScale => match image {
+                Some(image) => transform(num(0), num(1), |context| {
+                    context.scale(
+                        num(0) / image.width().max(1) as f64,
+                        num(1) / image.height().max(1) as f64,
+                    );
+                    context.set_source_surface(&*image, 0.0, 0.0)?;
+                    context.paint()
+                })?,
+                None => Value::Null,
+            },
+            Native::ImageRotate => match image {
+                Some(image) => {
+                    let (width, height) = (image.width() as f64, image.height() as f64);
+                    transform(width, height, |context| {
+                        context.translate(width / 2.0, height / 2.0);
+                        context.rotate(num(0));
+                        context.translate(-width / 2.0, -height / 2.0);
+                        context.set_source_surface(&*image, 0.0, 0.0)?;
+                        context.paint()
+                    })?
+                }
+                None => Value::Null,
+            },
+            Native::ImageTile => match image {
+                Some(image) => transform(num(0), num(1), |context| {
+                    context.set_source_surface(&*image, 0.0, 0.0)?;
+                    context.source().set_extend(cairo::Extend::Repeat);
+                    context.paint()
+                })?,
+                None => Value::Null,
+            },
+            Native::SpriteNew => {
+                let image = match arg(0) {
+                    Value::Image(image) => Some(image),
+                    _ => None,
+                };
+                let sprite = Rc::new(RefCell::new(Sprite {
+                    image,
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                    opacity: 1.0,
+                }));
+                self.sprites.push(Rc::downgrade(&sprite));
+                Value::Sprite(sprite)
+            }
+            Native::SpriteGetImage => sprite
+                .and_then(|s| s.borrow().image.clone())
+                .map_or(Value::Null, Value::Image),
+            Native::SpriteGetX => sprite.map_or(Value::Null, |s| Value::Number(s.borrow().x)),
+            Native::SpriteGetY => sprite.map_or(Value::Null, |s| Value::Number(s.borrow().y)),
+            Native::SpriteGetZ => sprite.map_or(Value::Null, |s| Value::Number(s.borrow().z)),
+            Native::SpriteGetOpacity => {
+                sprite.map_or(Value::Null, |s| Value::Number(s.borrow().opacity))
+            }
+            Native::SpriteSetImage
+            | Native::SpriteSetX
+            | Native::SpriteSetY
+            | Native::SpriteSetZ
+            | Native::SpriteSetPosition
+            | Native::SpriteSetOpacity => {
+                if let Some(sprite) = sprite {
+                    let mut sprite = sprite.borrow_mut();
+                    match native {
+                        Native::SpriteSetImage => {
+                            sprite.image = match arg(0) {
+                                Value::Image(image) => Some(image),
+                                _ => None,
+                            }
+                        }
+                        Native::SpriteSetX => sprite.x = num(0),
+                        Native::SpriteSetY => sprite.y = num(0),
+                        Native::SpriteSetZ => sprite.z = num(0),
+                        Native::SpriteSetOpacity => sprite.opacity = num(0).clamp(0.0, 1.0),
+                        _ => {
+                            sprite.x = num(0);
+                            sprite.y = num(1);
+                            if args.len() > 2 {
+                                sprite.z = num(2);
+                            }
+                        }
+                    }
+                }
+                Value::Null
+            }
+            Native::WindowGetWidth => Value::Number(self.window_size.0 as f64),
+            Native::WindowGetHeight => Value::Number(self.window_size.1 as f64),
+            Native::WindowGetX | Native::WindowGetY => Value::Number(0.0),
+            Native::WindowSetBackgroundTopColor => {
+                self.background_top = Some(color());
+                Value::Null
+            }
+            Native::WindowSetBackgroundBottomColor => {
+                self.background_bottom = Some(color());
+                Value::Null
+            }
+            Native::SetRefreshFunction
+            | Native::SetDisplayPasswordFunction
+            | Native::SetDisplayQuestionFunction
+            | Native::SetDisplayNormalFunction
+            | Native::SetMessageFunction => {
+                let callbacks = &mut self.callbacks;
+                let slot = match native {
+                    Native::SetRefreshFunction => &mut callbacks.refresh,
+                    Native::SetDisplayPasswordFunction => &mut callbacks.display_password,
+                    Native::SetDisplayQuestionFunction => &mut callbacks.display_question,
+                    Native::SetDisplayNormalFunction => &mut callbacks.display_normal,
+                    _ => &mut callbacks.message,
+                };
+                *slot = Some(arg(0)).filter(|f| matches!(f, Value::Fun(_)));
+                Value::Null
+            }
+            // The greeter runs once boot has finished.
+            Native::GetMode => Value::Str("boot".into()),
+            Native::MathAbs => Value::Number(num(0).abs()),
+            Native::MathMin => Value::Number(num(0).min(num(1))),
+            Native::MathMax => Value::Number(num(0).max(num(1))),
+            Native::MathSin => Value::Number(num(0).sin()),
+            Native::MathCos => Value::Number(num(0).cos()),
+            Native::MathSqrt => Value::Number(num(0).sqrt()),
+            Native::MathInt => Value::Number(num(0).trunc()),
+            Native::MathRandom => {
+                // xorshift64, plenty for twinkling stars
+                self.random ^= self.random << 13;
+                self.random ^= self.random >> 7;
+                self.random ^= self.random << 17;
+                Value::Number((self.random >> 11) as f64 / (1u64 << 53) as f64)
+            }
+        })
+    }
+}
+
+/// Draws into a new `width` x `height` image with `paint`.
+fn transform(
+    width: f64,
+    height: f64,
+    paint: impl FnOnce(&CairoContext) -> Result<(), cairo::Error>,
+) -> Result<Value, ScriptError> {
+    let runtime = |e: cairo::Error| ScriptError::Runtime(e.to_string());
+    let width = (width.round() as i32).max(1);
+    let height = (height.round() as i32).max(1);
+    let surface = ImageSurface::create(Format::ARgb32, width, height).map_err(runtime)?;
+    let context = CairoContext::new(&surface).map_err(runtime)?;
+    paint(&context).map_err(runtime)?;
+    drop(context);
+    Ok(Value::Image(Rc::new(surface)))
+}
+
+fn binary(op: BinOp, left: &Value, right: &Value) -> Value {
+    let bool = |b: bool| Value::Number(if b { 1.0 } else { 0.0 });
+    match op {
+        BinOp::Add => match (left, right) {
+            (Value::Number(a), Value::Number(b)) => Value::Number(a + b),
+            (Value::Str(_), _) | (_, Value::Str(_)) => {
+                let text = |v: &Value| v.text().unwrap_or_else(|| "#NULL".into());
+                Value::Str(format!("{}{}", text(left), text(right)).into())
+            }
+            _ => Value::Null,
+        },
+        BinOp::Sub => Value::Number(left.number() - right.number()),
+        BinOp::Mul => Value::Number(left.number() * right.number()),
+        BinOp::Div => Value::Number(left.number() / right.number()),
+        BinOp::Mod => Value::Number(left.number() % right.number()),
+        BinOp::Eq | BinOp::Ne => {
+            let equal = match (left, right) {
+                (Value::Null, Value::Null) => true,
+                (Value::Str(a), Value::Str(b)) => a == b,
+                (Value::Number(_), _) | (_, Value::Number(_)) => left.number() == right.number(),
+                (Value::Hash(a), Value::Hash(b)) => Rc::ptr_eq(a, b),
+                (Value::Image(a), Value::Image(b)) => Rc::ptr_eq(a, b),
+                (Value::Sprite(a), Value::Sprite(b)) => Rc::ptr_eq(a, b),
+                _ => false,
+            };
+            bool(equal == (op == BinOp::Eq))
+        }
+        BinOp::Lt => bool(left.number() < right.number()),
+        BinOp::Le => bool(left.number() <= right.number()),
+        BinOp::Gt => bool(left.number() > right.number()),
+        BinOp::Ge => bool(left.number() >= right.number()),
+        BinOp::And => bool(left.truthy() && right.truthy()),
+        BinOp::Or => bool(left.truthy() || right.truthy()),
+        BinOp::Extend => match (left, right) {
+            (Value::Null, _) => right.clone(),
+            (Value::Hash(a), Value::Hash(b)) => {
+                let mut fields = b.borrow().clone();
+                fields.extend(a.borrow().iter().map(|(k, v)| (k.clone(), v.clone())));
+                Value::Hash(Rc::new(RefCell::new(fields)))
+            }
+            _ => left.clone(),
+        },
+    }
+}
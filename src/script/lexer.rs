@@ -0,0 +1,132 @@
+use super::ScriptError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Number(f64),
+    Str(String),
+    Ident(String),
+    Punct(&'static str),
+    Eof,
+}
+
+/// Longest operators first, so `+=` is not read as `+` followed by `=`.
+const PUNCTUATION: [&str; 35] = [
+    "==", "!=", "<=", ">=", "&&", "||", "++", "--", "+=", "-=", "*=", "/=", "%=", "|=", "+", "-",
+    "*", "/", "%", "=", "<", ">", "!", "|", "(", ")", "{", "}", "[", "]", ";", ",", ".", "?", ":",
+];
+
+/// Splits a script into tokens, each tagged with the line it starts on.
+pub fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ScriptError> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = source.chars().collect();
+    let mut line = 1;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '#' || (c == '/' && chars.get(i + 1) == Some(&'/')) {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            let start = line;
+            i += 2;
+            loop {
+                match chars.get(i) {
+                    None => return Err(ScriptError::parse(start, "unterminated comment")),
+                    Some('*') if chars.get(i + 1) == Some(&'/') => break,
+                    Some('\n') => line += 1,
+                    _ => {}
+                }
+                i += 1;
+            }
+            i += 2;
+        } else if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(char::is_ascii_digit))
+        {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            let value = text
+                .parse()
+                .map_err(|_| ScriptError::parse(line, format!("bad number {text}")))?;
+            tokens.push((Token::Number(value), line));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push((Token::Ident(chars[start..i].iter().collect()), line));
+        } else if c == '"' {
+            let start = line;
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(ScriptError::parse(start, "unterminated string")),
+                    Some('"') => break,
+                    Some('\\') => {
+                        i += 1;
+                        match chars.get(i) {
+                            Some('n') => text.push('\n'),
+                            Some('t') => text.push('\t'),
+                            Some(&other) => text.push(other),
+                            None => {}
+                        }
+                    }
+                    Some(&other) => {
+                        if other == '\n' {
+                            line += 1;
+                        }
+                        text.push(other);
+                    }
+                }
+                i += 1;
+            }
+            i += 1;
+            tokens.push((Token::Str(text), start));
+        } else if let Some(p) = PUNCTUATION.iter().find(|p| {
+            p.len() <= chars.len() - i && p.chars().zip(&chars[i..]).all(|(a, b)| a == *b)
+        }) {
+            i += p.len();
+            tokens.push((Token::Punct(p), line));
+        } else {
+            return Err(ScriptError::parse(
+                line,
+                format!("unexpected character {c:?}"),
+            ));
+        }
+    }
+    tokens.push((Token::Eof, line));
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operators_comments_and_strings() {
+        let tokens = tokenize("a += .5; # note\n/* x\n */ s = \"a\\\"b\";").unwrap();
+        assert_eq!(
+            tokens,
+            [
+                (Token::Ident("a".into()), 1),
+                (Token::Punct("+="), 1),
+                (Token::Number(0.5), 1),
+                (Token::Punct(";"), 1),
+                (Token::Ident("s".into()), 3),
+                (Token::Punct("="), 3),
+                (Token::Str("a\"b".into()), 3),
+                (Token::Punct(";"), 3),
+                (Token::Eof, 3),
+            ]
+        );
+    }
+}
@@ -0,0 +1,415 @@
+use std::rc::Rc;
+
+use super::ScriptError;
+use super::lexer::{Token, tokenize};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+    /// `a | b`, plymouth's way of extending an object with another's fields.
+    Extend,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnOp {
+    Not,
+    Neg,
+    Plus,
+}
+
+#[derive(Debug)]
+pub enum Expr {
+    Number(f64),
+    Str(Rc<str>),
+    /// A plain name; `global`, `local` and `this` are resolved at run time.
+    Var(String),
+    Member(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    Fun(Rc<FunDef>),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// `target = value`, or `target op= value` when the operator is set.
+    Assign(Option<BinOp>, Box<Expr>, Box<Expr>),
+    /// `++`/`--`, before or after the target.
+    Step {
+        target: Box<Expr>,
+        delta: f64,
+        prefix: bool,
+    },
+}
+
+#[derive(Debug)]
+pub enum Stmt {
+    Expr(Expr),
+    Block(Vec<Stmt>),
+    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    While(Expr, Box<Stmt>),
+    For(Option<Expr>, Option<Expr>, Option<Expr>, Box<Stmt>),
+    Fun(String, Rc<FunDef>),
+    Return(Option<Expr>),
+    Break,
+    Continue,
+}
+
+#[derive(Debug)]
+pub struct FunDef {
+    pub params: Vec<String>,
+    pub body: Vec<Stmt>,
+}
+
+/// Parses a whole script into its top-level statements.
+pub fn parse(source: &str) -> Result<Vec<Stmt>, ScriptError> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        pos: 0,
+    };
+    let mut statements = Vec::new();
+    while parser.peek() != &Token::Eof {
+        statements.push(parser.statement()?);
+    }
+    Ok(statements)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].0.clone();
+        if token != Token::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error(&self, message: impl Into<String>) -> ScriptError {
+        ScriptError::parse(self.line(), message)
+    }
+
+    fn is(&self, punct: &str) -> bool {
+        matches!(self.peek(), Token::Punct(p) if *p == punct)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Token::Ident(name) if name == keyword)
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        let found = self.is(punct);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), ScriptError> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{punct}`, found {:?}", self.peek())))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, ScriptError> {
+        match self.next() {
+            Token::Ident(name) => Ok(name),
+            other => Err(self.error(format!("expected a name, found {other:?}"))),
+        }
+    }
+
+    fn statement(&mut self) -> Result<Stmt, ScriptError> {
+        if self.eat(";") {
+            return Ok(Stmt::Block(Vec::new()));
+        }
+        if self.eat("{") {
+            return Ok(Stmt::Block(self.block_rest()?));
+        }
+        let keyword = match self.peek() {
+            Token::Ident(name) => name.clone(),
+            _ => String::new(),
+        };
+        match keyword.as_str() {
+            "if" => {
+                self.pos += 1;
+                let condition = self.condition()?;
+                let then = Box::new(self.statement()?);
+                let otherwise = if self.is_keyword("else") {
+                    self.pos += 1;
+                    Some(Box::new(self.statement()?))
+                } else {
+                    None
+                };
+                Ok(Stmt::If(condition, then, otherwise))
+            }
+            "while" => {
+                self.pos += 1;
+                let condition = self.condition()?;
+                Ok(Stmt::While(condition, Box::new(self.statement()?)))
+            }
+            "for" => {
+                self.pos += 1;
+                self.expect("(")?;
+                let init = self.optional_expression(";")?;
+                let condition = self.optional_expression(";")?;
+                let step = self.optional_expression(")")?;
+                Ok(Stmt::For(
+                    init,
+                    condition,
+                    step,
+                    Box::new(self.statement()?),
+                ))
+            }
+            "fun" if matches!(self.tokens[self.pos + 1].0, Token::Ident(_)) => {
+                self.pos += 1;
+                let name = self.ident()?;
+                Ok(Stmt::Fun(name, self.function()?))
+            }
+            "return" => {
+                self.pos += 1;
+                let value = self.optional_expression(";")?;
+                Ok(Stmt::Return(value))
+            }
+            "break" | "continue" => {
+                self.pos += 1;
+                self.expect(";")?;
+                Ok(if keyword == "break" {
+                    Stmt::Break
+                } else {
+                    Stmt::Continue
+                })
+            }
+            _ => {
+                let expr = self.expression()?;
+                self.expect(";")?;
+                Ok(Stmt::Expr(expr))
+            }
+        }
+    }
+
+    fn block_rest(&mut self) -> Result<Vec<Stmt>, ScriptError> {
+        let mut statements = Vec::new();
+        while !self.eat("}") {
+            if self.peek() == &Token::Eof {
+                return Err(self.error("missing `}`"));
+            }
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn condition(&mut self) -> Result<Expr, ScriptError> {
+        self.expect("(")?;
+        let expr = self.expression()?;
+        self.expect(")")?;
+        Ok(expr)
+    }
+
+    /// An expression that may be left out before `end`, which is consumed.
+    fn optional_expression(&mut self, end: &str) -> Result<Option<Expr>, ScriptError> {
+        if self.eat(end) {
+            return Ok(None);
+        }
+        let expr = self.expression()?;
+        self.expect(end)?;
+        Ok(Some(expr))
+    }
+
+    /// Parameter list and body, after `fun` and the optional name.
+    fn function(&mut self) -> Result<Rc<FunDef>, ScriptError> {
+        self.expect("(")?;
+        let mut params = Vec::new();
+        while !self.eat(")") {
+            if !params.is_empty() {
+                self.expect(",")?;
+            }
+            params.push(self.ident()?);
+        }
+        self.expect("{")?;
+        let body = self.block_rest()?;
+        Ok(Rc::new(FunDef { params, body }))
+    }
+
+    fn expression(&mut self) -> Result<Expr, ScriptError> {
+        let target = self.ternary()?;
+        let op = match self.peek() {
+            Token::Punct("=") => None,
+            Token::Punct("+=") => Some(BinOp::Add),
+            Token::Punct("-=") => Some(BinOp::Sub),
+            Token::Punct("*=") => Some(BinOp::Mul),
+            Token::Punct("/=") => Some(BinOp::Div),
+            Token::Punct("%=") => Some(BinOp::Mod),
+            Token::Punct("|=") => Some(BinOp::Extend),
+            _ => return Ok(target),
+        };
+        if !matches!(target, Expr::Var(_) | Expr::Member(..) | Expr::Index(..)) {
+            return Err(self.error("cannot assign to this expression"));
+        }
+        self.pos += 1;
+        let value = self.expression()?;
+        Ok(Expr::Assign(op, Box::new(target), Box::new(value)))
+    }
+
+    fn ternary(&mut self) -> Result<Expr, ScriptError> {
+        let condition = self.binary(0)?;
+        if !self.eat("?") {
+            return Ok(condition);
+        }
+        let then = self.expression()?;
+        self.expect(":")?;
+        let otherwise = self.expression()?;
+        Ok(Expr::Ternary(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    /// Binary operators by precedence climbing, loosest level first.
+    fn binary(&mut self, level: usize) -> Result<Expr, ScriptError> {
+        const LEVELS: [&[(&str, BinOp)]; 7] = [
+            &[("||", BinOp::Or)],
+            &[("&&", BinOp::And)],
+            &[("|", BinOp::Extend)],
+            &[("==", BinOp::Eq), ("!=", BinOp::Ne)],
+            &[
+                ("<", BinOp::Lt),
+                ("<=", BinOp::Le),
+                (">", BinOp::Gt),
+                (">=", BinOp::Ge),
+            ],
+            &[("+", BinOp::Add), ("-", BinOp::Sub)],
+            &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Mod)],
+        ];
+        let Some(operators) = LEVELS.get(level) else {
+            return self.unary();
+        };
+        let mut left = self.binary(level + 1)?;
+        while let Some(&(_, op)) = operators.iter().find(|(p, _)| self.is(p)) {
+            self.pos += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ScriptError> {
+        let op = match self.peek() {
+            Token::Punct("!") => UnOp::Not,
+            Token::Punct("-") => UnOp::Neg,
+            Token::Punct("+") => UnOp::Plus,
+            Token::Punct(p @ ("++" | "--")) => {
+                let delta = if *p == "++" { 1.0 } else { -1.0 };
+                self.pos += 1;
+                let target = Box::new(self.unary()?);
+                return Ok(Expr::Step {
+                    target,
+                    delta,
+                    prefix: true,
+                });
+            }
+            _ => return self.postfix(),
+        };
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn postfix(&mut self) -> Result<Expr, ScriptError> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.eat(".") {
+                expr = Expr::Member(Box::new(expr), self.ident()?);
+            } else if self.eat("[") {
+                let index = self.expression()?;
+                self.expect("]")?;
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            } else if self.eat("(") {
+                let mut args = Vec::new();
+                while !self.eat(")") {
+                    if !args.is_empty() {
+                        self.expect(",")?;
+                    }
+                    args.push(self.expression()?);
+                }
+                expr = Expr::Call(Box::new(expr), args);
+            } else if self.is("++") || self.is("--") {
+                let delta = if self.is("++") { 1.0 } else { -1.0 };
+                self.pos += 1;
+                expr = Expr::Step {
+                    target: Box::new(expr),
+                    delta,
+                    prefix: false,
+                };
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ScriptError> {
+        match self.next() {
+            Token::Number(n) => Ok(Expr::Number(n)),
+            Token::Str(s) => Ok(Expr::Str(s.into())),
+            Token::Ident(name) if name == "fun" => Ok(Expr::Fun(self.function()?)),
+            Token::Ident(name) => Ok(Expr::Var(name)),
+            Token::Punct("(") => {
+                let expr = self.expression()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            other => Err(self.error(format!("unexpected {other:?}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precedence_and_statements() {
+        let program = parse(
+            "fun f(a, b) { return a + b * 2; }\n\
+             for (i = 0; i < 3; i++) if (!x) y.z[i] -= 1; else break;",
+        )
+        .unwrap();
+        let [Stmt::Fun(name, def), Stmt::For(..)] = &program[..] else {
+            panic!("unexpected program {program:?}");
+        };
+        assert_eq!(name, "f");
+        assert_eq!(def.params, ["a", "b"]);
+        let [Stmt::Return(Some(Expr::Binary(BinOp::Add, _, right)))] = &def.body[..] else {
+            panic!("unexpected body {:?}", def.body);
+        };
+        assert!(matches!(**right, Expr::Binary(BinOp::Mul, ..)));
+    }
+
+    #[test]
+    fn errors_carry_the_line() {
+        let err = parse("a = 1;\nb = (2;\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: expected `)`, found Punct(\";\")");
+    }
+}
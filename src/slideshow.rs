@@ -0,0 +1,163 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use cairo::ImageSurface;
+
+use crate::draw::load_png;
+
+/// Identifies the current boot, so a greeter restarted after logout keeps
+/// the picture it chose.
+const BOOT_ID: &str = "/proc/sys/kernel/random/boot_id";
+
+/// Background pictures taken in turn from a directory.
+///
+/// Pictures are decoded when they come up rather than all at once, since a
+/// directory of wallpapers can be large.
+pub struct Slideshow {
+    paths: Vec<PathBuf>,
+    current: usize,
+    /// How long each picture stays; `None` keeps the first one.
+    interval: Option<Duration>,
+    fade: Duration,
+    /// When the current picture was fully shown.
+    since: Instant,
+}
+
+impl Slideshow {
+    /// Reads the PNGs in `dir`, starting at one picked per boot. An
+    /// `interval` of zero seconds shows that picture only.
+    pub fn load(dir: &Path, interval: f32, fade: f32) -> Option<Self> {
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .ok()?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "png"))
+            .collect();
+        paths.sort();
+        let mut hasher = DefaultHasher::new();
+        match std::fs::read_to_string(BOOT_ID) {
+            Ok(boot_id) => boot_id.hash(&mut hasher),
+            Err(_) => Instant::now().hash(&mut hasher),
+        }
+        Self::new(paths, hasher.finish(), interval, fade)
+    }
+
+    fn new(paths: Vec<PathBuf>, seed: u64, interval: f32, fade: f32) -> Option<Self> {
+        if paths.is_empty() {
+            return None;
+        }
+        // Anything too long for a `Duration` is as good as forever.
+        let seconds = |s: f32| Duration::try_from_secs_f32(s.max(0.0)).unwrap_or(Duration::MAX);
+        Some(Self {
+            current: (seed % paths.len() as u64) as usize,
+            interval: Some(seconds(interval)).filter(|i| !i.is_zero() && paths.len() > 1),
+            fade: seconds(fade),
+            since: Instant::now(),
+            paths,
+        })
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Decodes picture `index`.
+    pub fn image(&self, index: usize) -> Option<ImageSurface> {
+        let path = &self.paths[index];
+        let image = load_png(path);
+        if image.is_none() {
+            eprintln!("could not load background image {}", path.display());
+        }
+        image
+    }
+
+    /// The picture fading in over the current one and how far along the
+    /// fade is, from 0 to 1.
+    pub fn fading(&self) -> Option<(usize, f64)> {
+        let shown = self.since.elapsed().checked_sub(self.interval?)?;
+        let progress = if self.fade.is_zero() {
+            1.0
+        } else {
+            (shown.as_secs_f64() / self.fade.as_secs_f64()).min(1.0)
+        };
+        Some(((self.current + 1) % self.paths.len(), progress))
+    }
+
+    /// Moves on to the next picture once it has faded in.
+    pub fn update(&mut self) {
+        if let Some(interval) = self.interval
+            && self.since.elapsed() >= interval.saturating_add(self.fade)
+        {
+            self.current = (self.current + 1) % self.paths.len();
+            self.since = Instant::now();
+        }
+    }
+
+    /// Time until the picture changes, or `frame` while it fades.
+    pub fn next_change_in(&self, frame: Duration) -> Option<Duration> {
+        let interval = self.interval?;
+        let elapsed = self.since.elapsed();
+        match interval.checked_sub(elapsed) {
+            Some(wait) if !wait.is_zero() => Some(wait),
+            _ => Some(frame.min(interval.saturating_add(self.fade).saturating_sub(elapsed))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slideshow(count: usize, seed: u64, interval: f32) -> Option<Slideshow> {
+        let paths = (0..count)
+            .map(|i| PathBuf::from(format!("{i}.png")))
+            .collect();
+        Slideshow::new(paths, seed, interval, 2.0)
+    }
+
+    #[test]
+    fn pictures_fade_into_the_next_on_a_timer() {
+        let frame = Duration::from_millis(40);
+        let mut show = slideshow(3, 5, 10.0).unwrap();
+        assert_eq!(show.current(), 2);
+        assert!(show.fading().is_none());
+        assert!(show.next_change_in(frame).unwrap() > Duration::from_secs(9));
+
+        show.since = Instant::now() - Duration::from_secs(11);
+        let (next, progress) = show.fading().unwrap();
+        assert_eq!(next, 0);
+        assert!((0.45..0.55).contains(&progress), "{progress}");
+        assert_eq!(show.next_change_in(frame), Some(frame));
+        show.update();
+        assert_eq!(show.current(), 2);
+
+        show.since = Instant::now() - Duration::from_secs(12);
+        show.update();
+        assert_eq!(show.current(), 0);
+        assert!(show.fading().is_none());
+    }
+
+    #[test]
+    fn a_zero_interval_keeps_the_boot_pick() {
+        let show = slideshow(4, 6, 0.0).unwrap();
+        assert_eq!(show.current(), 2);
+        assert!(show.fading().is_none());
+        assert!(show.next_change_in(Duration::from_millis(40)).is_none());
+        assert!(slideshow(1, 0, 5.0).unwrap().interval.is_none());
+        assert!(slideshow(0, 0, 5.0).is_none());
+    }
+
+    #[test]
+    fn huge_intervals_never_change_the_picture() {
+        let mut show = slideshow(2, 0, 1e20).unwrap();
+        assert_eq!(show.interval, Some(Duration::MAX));
+        assert!(show.fading().is_none());
+        show.update();
+        assert_eq!(show.current(), 0);
+        let frame = Duration::from_millis(40);
+        assert!(show.next_change_in(frame).unwrap() > Duration::from_secs(1 << 40));
+        let show = Slideshow::new(vec!["0.png".into(), "1.png".into()], 0, 5.0, f32::INFINITY);
+        assert_eq!(show.unwrap().fade, Duration::MAX);
+    }
+}
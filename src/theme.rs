@@ -0,0 +1,642 @@
+//! Theme files: plymouth `.plymouth` themes and native `.toml` ones.
+//!
+//! Plymouth themes are INI files: a `[Plymouth Theme]` section naming the
+//! theme and its `ModuleName`, and a section of that name with the plugin's
+//! keys.
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use pango::FontDescription;
+use thiserror::Error;
+
+use crate::background::{GradientDirection, ImageMode};
+use crate::color::Color;
+use crate::layout::Layout;
+
+mod native;
+
+const THEME_SECTION: &str = "Plymouth Theme";
+
+pub(crate) const DEFAULT_FONT: &str = "DejaVu Sans Mono,Monospace 18";
+const DEFAULT_SMALL_FONT: &str = "DejaVu Sans Mono,Monospace 14";
+pub(crate) const DEFAULT_TITLE_FONT: &str = "DejaVu Sans,Sans 24";
+
+/// Where plymouth installs themes, searched after any `--theme-dir`.
+pub const THEME_DIR: &str = "/usr/share/plymouth/themes";
+/// Files naming the system's theme, in the order plymouthd reads them.
+pub const PLYMOUTHD_CONFIGS: [&str; 2] = [
+    "/etc/plymouth/plymouthd.conf",
+    "/usr/share/plymouth/plymouthd.defaults",
+];
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum ThemeError {
+    #[error("could not read {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{}:{line}: {message}", file.display())]
+    Syntax {
+        file: PathBuf,
+        line: usize,
+        message: String,
+    },
+    #[error("{}:{line}: invalid {key}: {message}", file.display())]
+    Value {
+        file: PathBuf,
+        line: usize,
+        key: String,
+        message: String,
+    },
+    #[error("{}: {message}", file.display())]
+    Toml { file: PathBuf, message: String },
+    #[error("no theme named {name} in {}", join_paths(dirs))]
+    NotFound { name: String, dirs: Vec<PathBuf> },
+}
+
+fn join_paths(paths: &[PathBuf]) -> String {
+    let paths: Vec<_> = paths.iter().map(|p| p.display().to_string()).collect();
+    paths.join(", ")
+}
+
+/// A `key=value` line of a theme file.
+struct Entry {
+    key: String,
+    value: String,
+    line: usize,
+}
+
+struct Section {
+    name: String,
+    entries: Vec<Entry>,
+}
+
+/// Splits `source` into sections of trimmed entries. Lines before the
+/// first header go into a section with an empty name.
+fn parse_ini(source: &str, file: &Path) -> Result<Vec<Section>, ThemeError> {
+    let mut sections = vec![Section {
+        name: String::new(),
+        entries: Vec::new(),
+    }];
+    for (index, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        let syntax = |message: &str| ThemeError::Syntax {
+            file: file.to_path_buf(),
+            line: index + 1,
+            message: message.to_string(),
+        };
+        if let Some(header) = line.strip_prefix('[') {
+            let name = header
+                .strip_suffix(']')
+                .ok_or_else(|| syntax("section header is missing `]`"))?;
+            sections.push(Section {
+                name: name.trim().to_string(),
+                entries: Vec::new(),
+            });
+        } else if let Some((key, value)) = line.split_once('=') {
+            let entries = &mut sections.last_mut().expect("never empty").entries;
+            entries.push(Entry {
+                key: key.trim().to_string(),
+                value: value.trim().to_string(),
+                line: index + 1,
+            });
+        } else {
+            return Err(syntax("expected `[section]` or `key=value`"));
+        }
+    }
+    Ok(sections)
+}
+
+/// Replaces `@IMAGEDIR@` and `@THEMEDIR@`, the placeholders plymouth's own
+/// theme sources use, with the image and theme directories.
+fn expand(value: &str, image_dir: &str, theme_dir: &Path) -> String {
+    value
+        .replace("@IMAGEDIR@", image_dir)
+        .replace("@THEMEDIR@", &theme_dir.to_string_lossy())
+}
+
+/// Parses a pango font description such as `Cantarell Bold 16`.
+///
+/// A missing size is taken from `default`, whose families are appended so
+/// fontconfig falls back to them rather than to an arbitrary font when the
+/// theme's font is not installed.
+pub(crate) fn font_with_fallback(value: &str, default: &str) -> FontDescription {
+    let default = FontDescription::from_string(default);
+    let mut font = FontDescription::from_string(value);
+    if font.size() == 0 {
+        font.set_size(default.size());
+    }
+    let mut families: Vec<String> = Vec::new();
+    for list in [font.family(), default.family()].into_iter().flatten() {
+        for family in list.split(',').map(str::trim) {
+            if !family.is_empty() && !families.iter().any(|f| f.eq_ignore_ascii_case(family)) {
+                families.push(family.to_string());
+            }
+        }
+    }
+    font.set_family(&families.join(","));
+    font
+}
+
+/// Parses entry values, reporting failures with the file, line and key.
+struct Values<'a> {
+    file: &'a Path,
+}
+
+impl Values<'_> {
+    fn error(&self, entry: &Entry, message: impl ToString) -> ThemeError {
+        ThemeError::Value {
+            file: self.file.to_path_buf(),
+            line: entry.line,
+            key: entry.key.clone(),
+            message: message.to_string(),
+        }
+    }
+
+    fn parse<T: FromStr<Err: ToString>>(&self, entry: &Entry) -> Result<T, ThemeError> {
+        entry.value.parse().map_err(|e| self.error(entry, e))
+    }
+
+    fn number(&self, entry: &Entry) -> Result<f32, ThemeError> {
+        match entry.value.parse::<f32>() {
+            Ok(n) if n.is_finite() => Ok(n),
+            _ => Err(self.error(entry, format!("{:?} is not a number", entry.value))),
+        }
+    }
+
+    fn bool(&self, entry: &Entry) -> Result<bool, ThemeError> {
+        match entry.value.to_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Ok(true),
+            "false" | "no" | "off" | "0" => Ok(false),
+            _ => Err(self.error(entry, format!("{:?} is not true or false", entry.value))),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Module {
+    /// Prompt labels and typed text.
+    pub font: FontDescription,
+    /// The session line and messages.
+    pub small_font: FontDescription,
+    /// Set by `TitleFont`, which also turns on the theme name as title.
+    pub title_font: Option<FontDescription>,
+    pub image_dir: String,
+    /// The `.script` file of a `ModuleName=script` theme.
+    pub script_file: Option<String>,
+    pub dialog_horizontal_alignment: f32,
+    pub dialog_vertical_alignment: f32,
+    pub title_horizontal_alignment: f32,
+    pub title_vertical_alignment: f32,
+    pub watermark_horizontal_alignment: f32,
+    pub watermark_vertical_alignment: f32,
+    pub horizontal_alignment: f32,
+    pub vertical_alignment: f32,
+    pub background_start_color: Color,
+    pub background_end_color: Color,
+    pub background_gradient_direction: GradientDirection,
+    pub background_dither: bool,
+    /// PNG drawn over the background gradient.
+    pub background_image: Option<String>,
+    pub background_image_mode: ImageMode,
+    /// Color, usually translucent, painted over the background image.
+    pub background_tint: Option<Color>,
+    /// Blur radius of the background image, in pixels.
+    pub background_blur: f32,
+    /// Directory of PNGs shown in turn instead of `background_image`.
+    pub background_slideshow: Option<String>,
+    /// Seconds each slideshow picture stays; zero shows one per boot.
+    pub background_slideshow_interval: f32,
+    /// Seconds one slideshow picture takes to fade into the next.
+    pub background_slideshow_fade: f32,
+    /// Text of the `Title` key, shown instead of the theme name.
+    pub title: Option<String>,
+    pub text_color: Color,
+    /// The field being typed into.
+    pub focused_text_color: Color,
+    /// Text typed into a two-step `entry.png`.
+    pub input_text_color: Color,
+    pub session_color: Color,
+    pub title_color: Color,
+    pub message_info_color: Color,
+    pub message_error_color: Color,
+    /// Seconds before a message disappears; zero keeps it until the next one.
+    pub message_timeout: f32,
+    /// Frames per second of the throbber and animation sequences.
+    pub animation_frame_rate: f32,
+}
+
+impl Default for Module {
+    fn default() -> Self {
+        Module {
+            font: FontDescription::from_string(DEFAULT_FONT),
+            small_font: FontDescription::from_string(DEFAULT_SMALL_FONT),
+            title_font: None,
+            image_dir: "".to_string(),
+            script_file: None,
+            dialog_horizontal_alignment: 0f32,
+            dialog_vertical_alignment: 0f32,
+            title_horizontal_alignment: 0f32,
+            title_vertical_alignment: 0f32,
+            watermark_horizontal_alignment: 0f32,
+            watermark_vertical_alignment: 0f32,
+            horizontal_alignment: 0f32,
+            vertical_alignment: 0f32,
+            background_start_color: Color::default(),
+            background_end_color: Color::default(),
+            background_gradient_direction: GradientDirection::default(),
+            background_dither: true,
+            background_image: None,
+            background_image_mode: ImageMode::default(),
+            background_tint: None,
+            background_blur: 0.0,
+            background_slideshow: None,
+            background_slideshow_interval: 0.0,
+            background_slideshow_fade: 1.0,
+            title: None,
+            text_color: Color::WHITE,
+            focused_text_color: Color::YELLOW,
+            input_text_color: Color::BLACK,
+            session_color: Color::YELLOW,
+            title_color: Color::WHITE,
+            message_info_color: Color::WHITE,
+            message_error_color: Color::RED,
+            message_timeout: 5.0,
+            animation_frame_rate: 30.0,
+        }
+    }
+}
+
+impl Module {
+    fn from_entries(entries: &[Entry], values: &Values<'_>) -> Result<Self, ThemeError> {
+        let mut module = Module::default();
+        let mut has_end_color = false;
+        let mut has_small_font = false;
+        for entry in entries {
+            let value = entry.value.clone();
+            match entry.key.as_str() {
+                "Font" => module.font = font_with_fallback(&value, DEFAULT_FONT),
+                "SmallFont" => {
+                    module.small_font = font_with_fallback(&value, DEFAULT_SMALL_FONT);
+                    has_small_font = true;
+                }
+                "TitleFont" => {
+                    module.title_font = Some(font_with_fallback(&value, DEFAULT_TITLE_FONT))
+                }
+                "ImageDir" => module.image_dir = value,
+                "ScriptFile" => module.script_file = Some(value),
+                "Title" => module.title = Some(value),
+                "DialogHorizontalAlignment" => {
+                    module.dialog_horizontal_alignment = values.number(entry)?
+                }
+                "DialogVerticalAlignment" => {
+                    module.dialog_vertical_alignment = values.number(entry)?
+                }
+                "TitleHorizontalAlignment" => {
+                    module.title_horizontal_alignment = values.number(entry)?
+                }
+                "TitleVerticalAlignment" => {
+                    module.title_vertical_alignment = values.number(entry)?
+                }
+                "HorizontalAlignment" => module.horizontal_alignment = values.number(entry)?,
+                "VerticalAlignment" => module.vertical_alignment = values.number(entry)?,
+                "WatermarkHorizontalAlignment" => {
+                    module.watermark_horizontal_alignment = values.number(entry)?
+                }
+                "WatermarkVerticalAlignment" => {
+                    module.watermark_vertical_alignment = values.number(entry)?
+                }
+                "BackgroundStartColor" => module.background_start_color = values.parse(entry)?,
+                "BackgroundEndColor" => {
+                    module.background_end_color = values.parse(entry)?;
+                    has_end_color = true;
+                }
+                "BackgroundGradientDirection" => {
+                    module.background_gradient_direction = values.parse(entry)?
+                }
+                "BackgroundDither" => module.background_dither = values.bool(entry)?,
+                "BackgroundImage" => module.background_image = Some(value),
+                "BackgroundImageMode" => module.background_image_mode = values.parse(entry)?,
+                "BackgroundTint" => module.background_tint = Some(values.parse(entry)?),
+                "BackgroundBlur" => module.background_blur = values.number(entry)?,
+                "BackgroundSlideshow" => module.background_slideshow = Some(value),
+                "BackgroundSlideshowInterval" => {
+                    module.background_slideshow_interval = values.number(entry)?
+                }
+                "BackgroundSlideshowFade" => {
+                    module.background_slideshow_fade = values.number(entry)?
+                }
+                "TextColor" => module.text_color = values.parse(entry)?,
+                "FocusedTextColor" => module.focused_text_color = values.parse(entry)?,
+                "InputTextColor" => module.input_text_color = values.parse(entry)?,
+                "SessionColor" => module.session_color = values.parse(entry)?,
+                "TitleColor" => module.title_color = values.parse(entry)?,
+                "MessageInfoColor" => module.message_info_color = values.parse(entry)?,
+                "MessageErrorColor" => module.message_error_color = values.parse(entry)?,
+                "MessageTimeout" => module.message_timeout = values.number(entry)?,
+                "AnimationFrameRate" => module.animation_frame_rate = values.number(entry)?,
+                _ => {}
+            }
+        }
+        if !has_end_color {
+            // A theme with a single color gets a flat background.
+            module.background_end_color = module.background_start_color;
+        }
+        if !has_small_font {
+            // Same face as the prompt, in the default proportion to it.
+            module.small_font = module.font.clone();
+            module.small_font.set_size(module.font.size() * 7 / 9);
+        }
+        Ok(module)
+    }
+}
+
+#[derive(Clone)]
+pub struct Theme {
+    pub name: String,
+    pub description: Option<String>,
+    pub module: Module,
+    pub layout: Layout,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        let module = Module::default();
+        Theme {
+            name: String::new(),
+            description: None,
+            layout: Layout::plymouth(&module),
+            module,
+        }
+    }
+}
+
+impl Theme {
+    pub fn from_file(path: &Path) -> Result<Self, ThemeError> {
+        let source = std::fs::read_to_string(path).map_err(|source| ThemeError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            native::parse(&source, path)
+        } else {
+            Self::parse(&source, path)
+        }
+    }
+
+    /// Parses the contents of the theme file at `file`, which is used in
+    /// errors and for `@THEMEDIR@`.
+    pub fn parse(source: &str, file: &Path) -> Result<Self, ThemeError> {
+        let mut sections = parse_ini(source, file)?;
+        let theme_dir = file.parent().unwrap_or(Path::new(""));
+        let module_name = sections
+            .iter()
+            .filter(|s| s.name == THEME_SECTION)
+            .flat_map(|s| &s.entries)
+            .rfind(|e| e.key == "ModuleName")
+            .map(|e| e.value.clone());
+        // `@IMAGEDIR@` is the module's own `ImageDir`, even in other sections.
+        let image_dir = sections
+            .iter()
+            .filter(|s| Some(&s.name) == module_name.as_ref())
+            .flat_map(|s| &s.entries)
+            .find(|e| e.key == "ImageDir")
+            .map(|e| expand(&e.value, "", theme_dir))
+            .unwrap_or_default();
+        for entry in sections.iter_mut().flat_map(|s| &mut s.entries) {
+            entry.value = expand(&entry.value, &image_dir, theme_dir);
+        }
+
+        let values = Values { file };
+        let mut theme = Theme::default();
+        let header = sections.iter().filter(|s| s.name == THEME_SECTION);
+        for entry in header.flat_map(|s| &s.entries) {
+            match entry.key.as_str() {
+                "Name" => theme.name = entry.value.clone(),
+                "Description" => theme.description = Some(entry.value.clone()),
+                _ => {}
+            }
+        }
+        if let Some(module_name) = module_name {
+            let entries: Vec<_> = sections
+                .into_iter()
+                .filter(|s| s.name == module_name)
+                .flat_map(|s| s.entries)
+                .collect();
+            theme.module = Module::from_entries(&entries, &values)?;
+            let module = &mut theme.module;
+            for path in [
+                &mut module.background_image,
+                &mut module.background_slideshow,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                *path = theme_dir.join(&*path).to_string_lossy().into_owned();
+            }
+            theme.layout = Layout::plymouth(&theme.module);
+        }
+        Ok(theme)
+    }
+}
+
+/// Finds `<dir>/<name>/<name>.toml`, or else `<name>.plymouth`, in the
+/// first of `dirs` that has either.
+pub fn find_theme(name: &str, dirs: &[PathBuf]) -> Result<PathBuf, ThemeError> {
+    dirs.iter()
+        .flat_map(|dir| {
+            ["toml", "plymouth"].map(|ext| dir.join(name).join(format!("{name}.{ext}")))
+        })
+        .find(|path| path.is_file())
+        .ok_or_else(|| ThemeError::NotFound {
+            name: name.to_string(),
+            dirs: dirs.to_vec(),
+        })
+}
+
+/// The theme the machine boots with: the `Theme` key of the first
+/// plymouthd config that sets one, or else the `default.plymouth` link
+/// plymouth-set-default-theme maintains.
+pub fn system_theme(configs: &[PathBuf], dirs: &[PathBuf]) -> Option<PathBuf> {
+    let configured = configs.iter().find_map(|config| {
+        let source = std::fs::read_to_string(config).ok()?;
+        let sections = parse_ini(&source, config).ok()?;
+        let daemon = sections.into_iter().filter(|s| s.name == "Daemon");
+        let entry = daemon.flat_map(|s| s.entries).find(|e| e.key == "Theme")?;
+        Some(entry.value)
+    });
+    if let Some(name) = configured {
+        match find_theme(&name, dirs) {
+            Ok(path) => return Some(path),
+            Err(err) => eprintln!("{err}"),
+        }
+    }
+    dirs.iter()
+        .map(|dir| dir.join("default.plymouth"))
+        .find_map(|link| std::fs::canonicalize(link).ok())
+}
+
+impl FromStr for Theme {
+    type Err = ThemeError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s, Path::new("<theme>"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<Theme, ThemeError> {
+        Theme::parse(source, Path::new("/themes/test/test.plymouth"))
+    }
+
+    #[test]
+    fn only_the_named_module_section_is_read() {
+        let theme = parse(
+            "[Plymouth Theme]\n\
+             Name = Test\n\
+             ModuleName=two-step\n\
+             \n\
+             [script]\n\
+             DialogVerticalAlignment=.9\n\
+             \n\
+             [two-step]\n\
+             ; plymouth keys\n\
+             DialogHorizontalAlignment = 0.5\n\
+             DialogVerticalAlignment=1\n\
+             BackgroundStartColor = 0x102030\n",
+        )
+        .unwrap();
+        assert_eq!(theme.name, "Test");
+        assert_eq!(theme.module.dialog_horizontal_alignment, 0.5);
+        assert_eq!(theme.module.dialog_vertical_alignment, 1.0);
+        assert_eq!(
+            theme.module.background_start_color.green,
+            0x20 as f32 / 255.0
+        );
+    }
+
+    #[test]
+    fn fonts_fall_back_to_the_defaults() {
+        let theme = parse(
+            "[Plymouth Theme]\nModuleName=two-step\n[two-step]\n\
+             Font=Cantarell Bold\nTitleFont=monospace 30\n",
+        )
+        .unwrap();
+        let module = theme.module;
+        assert_eq!(
+            module.font.to_string(),
+            "Cantarell,DejaVu Sans Mono,Monospace Bold 18"
+        );
+        assert_eq!(module.small_font.size(), 14 * pango::SCALE);
+        assert_eq!(
+            module.title_font.unwrap().to_string(),
+            "monospace,DejaVu Sans,Sans 30"
+        );
+        let default = Module::default();
+        assert_eq!(default.font.to_string(), "DejaVu Sans Mono,Monospace 18");
+        assert!(default.title_font.is_none());
+    }
+
+    #[test]
+    fn placeholders_are_expanded() {
+        let theme = parse(
+            "[Plymouth Theme]\nModuleName=script\n\n[script]\n\
+             ScriptFile=@IMAGEDIR@/test.script\nImageDir=@THEMEDIR@/images\n",
+        )
+        .unwrap();
+        assert_eq!(theme.module.image_dir, "/themes/test/images");
+        assert_eq!(
+            theme.module.script_file.as_deref(),
+            Some("/themes/test/images/test.script")
+        );
+        let theme = parse(
+            "[Plymouth Theme]\nModuleName=two-step\n[two-step]\n\
+             BackgroundImage=wallpaper.png\nBackgroundImageMode=fit\nBackgroundTint=0x00000040\n",
+        )
+        .unwrap();
+        assert_eq!(
+            theme.module.background_image.as_deref(),
+            Some("/themes/test/wallpaper.png")
+        );
+        assert_eq!(theme.module.background_image_mode, ImageMode::Fit);
+        // Another module's image directory is not this one's.
+        let theme = parse(
+            "[Plymouth Theme]\nModuleName=script\n\n[two-step]\nImageDir=/elsewhere\n\n\
+             [script]\nImageDir=@THEMEDIR@\nScriptFile=@IMAGEDIR@/test.script\n",
+        )
+        .unwrap();
+        assert_eq!(theme.module.image_dir, "/themes/test");
+        assert_eq!(
+            theme.module.script_file.as_deref(),
+            Some("/themes/test/test.script")
+        );
+    }
+
+    #[test]
+    fn themes_are_found_by_name_and_system_default() {
+        let root = std::env::temp_dir().join(format!("ndlm-themes-{}", std::process::id()));
+        let (local, system) = (root.join("local"), root.join("system"));
+        for (dir, name) in [
+            (&local, "custom"),
+            (&system, "custom"),
+            (&system, "spinner"),
+        ] {
+            std::fs::create_dir_all(dir.join(name)).unwrap();
+            std::fs::write(dir.join(name).join(format!("{name}.plymouth")), "").unwrap();
+        }
+        let dirs = [local.clone(), system.clone()];
+        assert_eq!(
+            find_theme("custom", &dirs).unwrap(),
+            local.join("custom/custom.plymouth")
+        );
+        std::fs::write(local.join("custom/custom.toml"), "").unwrap();
+        assert_eq!(
+            find_theme("custom", &dirs).unwrap(),
+            local.join("custom/custom.toml")
+        );
+        assert!(find_theme("missing", &dirs).is_err());
+
+        let conf = root.join("plymouthd.conf");
+        std::fs::write(&conf, "[Daemon]\nTheme=spinner\nShowDelay=0\n").unwrap();
+        assert_eq!(
+            system_theme(&[root.join("missing.conf"), conf], &dirs),
+            Some(system.join("spinner/spinner.plymouth"))
+        );
+        std::os::unix::fs::symlink(
+            system.join("custom/custom.plymouth"),
+            system.join("default.plymouth"),
+        )
+        .unwrap();
+        assert_eq!(
+            system_theme(&[], &dirs),
+            Some(system.join("custom/custom.plymouth"))
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn errors_name_the_file_line_and_key() {
+        let err =
+            parse("[Plymouth Theme]\nModuleName=two-step\n[two-step]\nMessageErrorColor=red\n")
+                .err()
+                .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "/themes/test/test.plymouth:4: invalid MessageErrorColor: \
+             invalid color \"red\", expected 0xRRGGBB or 0xRRGGBBAA"
+        );
+        let err = parse("[Plymouth Theme\n").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "/themes/test/test.plymouth:1: section header is missing `]`"
+        );
+    }
+}
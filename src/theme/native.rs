@@ -0,0 +1,282 @@
+//! Native `.toml` themes.
+//!
+//! The `[style]` table holds the same settings as the plymouth keys, in
+//! snake case, and each `[[widget]]` places one element of the screen.
+
+use std::path::Path;
+
+use serde::Deserialize;
+
+use super::{
+    DEFAULT_FONT, DEFAULT_SMALL_FONT, DEFAULT_TITLE_FONT, Module, Theme, ThemeError,
+    font_with_fallback,
+};
+use crate::background::{GradientDirection, ImageMode};
+use crate::color::Color;
+use crate::layout::{Layout, Widget, WidgetKind};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NativeTheme {
+    #[serde(default)]
+    name: String,
+    description: Option<String>,
+    #[serde(default)]
+    style: Style,
+    #[serde(default)]
+    widget: Vec<Widget>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Style {
+    font: Option<String>,
+    small_font: Option<String>,
+    title_font: Option<String>,
+    /// Relative to the theme file.
+    image_dir: Option<String>,
+    title: Option<String>,
+    background_start_color: Option<Color>,
+    background_end_color: Option<Color>,
+    background_gradient_direction: Option<GradientDirection>,
+    background_dither: Option<bool>,
+    /// Relative to the theme file.
+    background_image: Option<String>,
+    background_image_mode: Option<ImageMode>,
+    background_tint: Option<Color>,
+    background_blur: Option<f32>,
+    /// Relative to the theme file.
+    background_slideshow: Option<String>,
+    background_slideshow_interval: Option<f32>,
+    background_slideshow_fade: Option<f32>,
+    text_color: Option<Color>,
+    focused_text_color: Option<Color>,
+    input_text_color: Option<Color>,
+    session_color: Option<Color>,
+    title_color: Option<Color>,
+    message_info_color: Option<Color>,
+    message_error_color: Option<Color>,
+    message_timeout: Option<f32>,
+    animation_frame_rate: Option<f32>,
+}
+
+impl Style {
+    /// The first number that is infinite or NaN, which TOML allows but the
+    /// plymouth keys reject.
+    fn non_finite(&self) -> Option<(&'static str, f32)> {
+        [
+            ("background_blur", self.background_blur),
+            (
+                "background_slideshow_interval",
+                self.background_slideshow_interval,
+            ),
+            ("background_slideshow_fade", self.background_slideshow_fade),
+            ("message_timeout", self.message_timeout),
+            ("animation_frame_rate", self.animation_frame_rate),
+        ]
+        .into_iter()
+        .find_map(|(key, value)| Some((key, value?)).filter(|(_, value)| !value.is_finite()))
+    }
+
+    /// The style over the defaults, with the same fallbacks as plymouth
+    /// themes get for a missing end color or small font.
+    fn into_module(self, theme_dir: &Path) -> Module {
+        let mut module = Module::default();
+        if let Some(font) = &self.font {
+            module.font = font_with_fallback(font, DEFAULT_FONT);
+        }
+        module.small_font = match &self.small_font {
+            Some(font) => font_with_fallback(font, DEFAULT_SMALL_FONT),
+            None => {
+                let mut font = module.font.clone();
+                font.set_size(module.font.size() * 7 / 9);
+                font
+            }
+        };
+        module.title_font = self
+            .title_font
+            .map(|font| font_with_fallback(&font, DEFAULT_TITLE_FONT));
+        if let Some(dir) = self.image_dir {
+            module.image_dir = theme_dir.join(dir).to_string_lossy().into_owned();
+        }
+        module.title = self.title;
+        module.background_image = self
+            .background_image
+            .map(|image| theme_dir.join(image).to_string_lossy().into_owned());
+        module.background_image_mode = self.background_image_mode.unwrap_or_default();
+        module.background_tint = self.background_tint;
+        module.background_blur = self.background_blur.unwrap_or_default();
+        module.background_slideshow = self
+            .background_slideshow
+            .map(|dir| theme_dir.join(dir).to_string_lossy().into_owned());
+        if let Some(interval) = self.background_slideshow_interval {
+            module.background_slideshow_interval = interval;
+        }
+        if let Some(fade) = self.background_slideshow_fade {
+            module.background_slideshow_fade = fade;
+        }
+        let start = self.background_start_color.unwrap_or_default();
+        module.background_start_color = start;
+        module.background_end_color = self.background_end_color.unwrap_or(start);
+        let set = |value: Option<Color>, color: &mut Color| {
+            if let Some(value) = value {
+                *color = value;
+            }
+        };
+        set(self.text_color, &mut module.text_color);
+        set(self.focused_text_color, &mut module.focused_text_color);
+        set(self.input_text_color, &mut module.input_text_color);
+        set(self.session_color, &mut module.session_color);
+        set(self.title_color, &mut module.title_color);
+        set(self.message_info_color, &mut module.message_info_color);
+        set(self.message_error_color, &mut module.message_error_color);
+        if let Some(direction) = self.background_gradient_direction {
+            module.background_gradient_direction = direction;
+        }
+        if let Some(dither) = self.background_dither {
+            module.background_dither = dither;
+        }
+        if let Some(timeout) = self.message_timeout {
+            module.message_timeout = timeout;
+        }
+        if let Some(rate) = self.animation_frame_rate {
+            module.animation_frame_rate = rate;
+        }
+        module
+    }
+}
+
+/// Parses a native theme. One without widgets gets the plymouth layout.
+pub(super) fn parse(source: &str, file: &Path) -> Result<Theme, ThemeError> {
+    let theme: NativeTheme = toml::from_str(source).map_err(|err| ThemeError::Toml {
+        file: file.to_path_buf(),
+        message: err.to_string(),
+    })?;
+    if let Some((key, value)) = theme.style.non_finite() {
+        return Err(ThemeError::Toml {
+            file: file.to_path_buf(),
+            message: format!("{key} = {value} is not a number"),
+        });
+    }
+    let theme_dir = file.parent().unwrap_or(Path::new(""));
+    let module = theme.style.into_module(theme_dir);
+    let mut widgets = theme.widget;
+    for widget in &mut widgets {
+        if let Some(path) = &widget.path {
+            widget.path = Some(theme_dir.join(path));
+        }
+        if widget.kind == WidgetKind::Image && widget.path.is_none() {
+            return Err(ThemeError::Toml {
+                file: file.to_path_buf(),
+                message: "image widget without a path".to_string(),
+            });
+        }
+    }
+    let layout = if widgets.is_empty() {
+        Layout::plymouth(&module)
+    } else {
+        Layout { widgets }
+    };
+    Ok(Theme {
+        name: theme.name,
+        description: theme.description,
+        module,
+        layout,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{Anchor, TextAlign};
+
+    const THEME: &str = r##"
+name = "Midnight"
+description = "Dark panel in the middle"
+
+[style]
+font = "Cantarell 16"
+image_dir = "images"
+background_start_color = "#10131a"
+focused_text_color = "0x88c0d0"
+
+[[widget]]
+type = "background"
+anchor = "center"
+width = 420
+height = 160
+background = "0x2e344099"
+radius = 12
+z = -1
+
+[[widget]]
+type = "username"
+anchor = [0.5, 0.45]
+text = "User "
+align = "center"
+width = 380
+
+[[widget]]
+type = "image"
+anchor = "top-right"
+x = -16
+y = 16
+path = "images/logo.png"
+"##;
+
+    #[test]
+    fn widgets_and_style_are_read() {
+        let theme = parse(THEME, Path::new("/themes/midnight/midnight.toml")).unwrap();
+        assert_eq!(theme.name, "Midnight");
+        let module = &theme.module;
+        assert_eq!(
+            module.font.to_string(),
+            "Cantarell,DejaVu Sans Mono,Monospace 16"
+        );
+        assert_eq!(module.small_font.size(), 16 * pango::SCALE * 7 / 9);
+        assert_eq!(module.image_dir, "/themes/midnight/images");
+        assert_eq!(module.background_end_color.blue, 0x1a as f32 / 255.0);
+        let [panel, username, logo] = &theme.layout.widgets[..] else {
+            panic!("expected three widgets");
+        };
+        assert_eq!(panel.kind, WidgetKind::Background);
+        assert_eq!(panel.background.unwrap().opacity, 0x99 as f32 / 255.0);
+        assert_eq!((panel.radius, panel.z()), (12.0, -1));
+        assert_eq!(username.anchor, Anchor::new(0.5, 0.45));
+        assert_eq!(username.align, TextAlign::Center);
+        assert_eq!(
+            logo.path.as_deref(),
+            Some(Path::new("/themes/midnight/images/logo.png"))
+        );
+    }
+
+    #[test]
+    fn errors_name_the_file_and_line() {
+        let file = Path::new("/themes/bad/bad.toml");
+        let err = parse("[[widget]]\ntype = \"clock\"\ncolor = \"red\"\n", file)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.starts_with("/themes/bad/bad.toml: TOML parse error at line 3"));
+        assert!(err.contains("invalid color \"red\""), "{err}");
+        let err = parse("[[widget]]\ntype = \"image\"\n", file).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "/themes/bad/bad.toml: image widget without a path"
+        );
+        let err = parse("[style]\nbackground_slideshow_interval = inf\n", file)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "/themes/bad/bad.toml: background_slideshow_interval = inf is not a number"
+        );
+    }
+
+    #[test]
+    fn themes_without_widgets_use_the_plymouth_layout() {
+        let theme = parse("name = \"Plain\"\n", Path::new("plain.toml")).unwrap();
+        assert!(theme.layout.has(WidgetKind::Username));
+        assert!(theme.layout.has(WidgetKind::Session));
+    }
+}